@@ -0,0 +1,226 @@
+use std::ffi::OsString;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::cmdui::CmdApp;
+use crate::cmdline::Arg;
+use crate::error::CmdError;
+use crate::parse;
+
+// Conversion of an argument value into a typed value. EXPECTED describes
+// the type in error messages, as in "Expected integer, got 'x'".
+pub trait FromArg: Sized {
+    const EXPECTED: &'static str;
+
+    fn from_arg(value: &str) -> Option<Self>;
+}
+
+macro_rules! from_arg_parse {
+    ($expected:expr, $($t:ty),*) => {
+        $(
+            impl FromArg for $t {
+                const EXPECTED: &'static str = $expected;
+
+                fn from_arg(value: &str) -> Option<Self> {
+                    return value.parse().ok();
+                }
+            }
+        )*
+    }
+}
+
+// Integers may be written in hex, octal or binary, with a 0x, 0o or 0b
+// prefix
+macro_rules! from_arg_int {
+    ($expected:expr, $($t:ty),*) => {
+        $(
+            impl FromArg for $t {
+                const EXPECTED: &'static str = $expected;
+
+                fn from_arg(value: &str) -> Option<Self> {
+                    let (digits, radix) = parse::radix(value)?;
+                    return <$t>::from_str_radix(&digits, radix).ok();
+                }
+            }
+        )*
+    }
+}
+
+from_arg_int!("integer", usize, u8, u16, u32, u64, u128);
+from_arg_int!("signed integer", isize, i8, i16, i32, i64, i128);
+from_arg_parse!("number", f32, f64);
+from_arg_parse!("character", char);
+from_arg_parse!("string", String, PathBuf, OsString);
+
+// A number with a unit, e.g. "250ms" or "1h30m"
+impl FromArg for Duration {
+    const EXPECTED: &'static str = "duration";
+
+    fn from_arg(value: &str) -> Option<Self> {
+        return parse::duration(value);
+    }
+}
+
+impl FromArg for bool {
+    const EXPECTED: &'static str = "boolean";
+
+    fn from_arg(value: &str) -> Option<Self> {
+        return <dyn CmdApp>::parse_bool(value).ok();
+    }
+}
+
+// The arguments of a command line, after the command keywords. Arguments
+// can be accessed by position, or by the name of the placeholder they were
+// matched against in the command pattern, e.g. args.get::<usize>("count")
+// for "<count>".
+pub struct Args {
+    args: Vec<Arg>,
+    // Placeholder name and argument index
+    names: Vec<(String, usize)>,
+}
+
+impl Args {
+    pub(crate) fn new(args: Vec<Arg>, names: Vec<(String, usize)>) -> Self {
+        Self {
+            args: args,
+            names: names,
+        }
+    }
+
+    // All arguments matched against the named placeholder
+    pub fn values(&self, name: &str) -> Vec<&Arg> {
+        return self.names.iter()
+            .filter(|(n, _)| n == name)
+            .map(|(_, i)| &self.args[*i])
+            .collect();
+    }
+
+    // The argument matched against the named placeholder, if any
+    pub fn value(&self, name: &str) -> Option<&Arg> {
+        return self.values(name).into_iter().next();
+    }
+
+    pub fn get<T: FromArg>(&self, name: &str) -> Result<T, CmdError> {
+        return self.get_opt(name)?.ok_or_else(|| {
+            CmdError::argument(&format!("Missing value for <{}>", name))
+        });
+    }
+
+    pub fn get_opt<T: FromArg>(&self, name: &str)
+                               -> Result<Option<T>, CmdError> {
+        return self.value(name)
+            .map(|a| convert(name, a))
+            .transpose();
+    }
+
+    // Values of a repeated placeholder, as in "<file>..."
+    pub fn get_all<T: FromArg>(&self, name: &str)
+                               -> Result<Vec<T>, CmdError> {
+        return self.values(name).into_iter()
+            .map(|a| convert(name, a))
+            .collect();
+    }
+}
+
+fn convert<T: FromArg>(name: &str, arg: &Arg) -> Result<T, CmdError> {
+    return T::from_arg(arg.as_str()).ok_or_else(|| {
+        CmdError::argument(&format!("Expected {} for <{}>, got '{}'",
+                                    T::EXPECTED, name, arg.as_str()))
+    });
+}
+
+impl Deref for Args {
+    type Target = [Arg];
+
+    fn deref(&self) -> &[Arg] {
+        return &self.args;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmdline::CommandLine;
+    use crate::error::CmdErrorKind;
+
+    // Arguments from a line, each named after the placeholder at the same
+    // position in names
+    fn args(line: &str, names: &[&str]) -> Args {
+        let cl = CommandLine::new(line.to_string());
+        let args = cl.parse().unwrap().iter().map(|p| p.to_arg()).collect();
+        let names = names.iter()
+            .enumerate()
+            .map(|(i, n)| (n.to_string(), i))
+            .collect();
+
+        return Args::new(args, names);
+    }
+
+    #[test]
+    fn get_converts_values() {
+        let a = args("42 0x10 on 1.5s 'a b'",
+                     &["int", "hex", "bool", "time", "text"]);
+
+        assert_eq!(a.get::<usize>("int").unwrap(), 42);
+        assert_eq!(a.get::<u8>("hex").unwrap(), 16);
+        assert!(a.get::<bool>("bool").unwrap());
+        assert_eq!(a.get::<Duration>("time").unwrap(),
+                   Duration::from_millis(1500));
+        assert_eq!(a.get::<String>("text").unwrap(), "a b");
+        assert_eq!(a.len(), 5);
+        assert_eq!(a[4].raw(), "'a b'");
+    }
+
+    #[test]
+    fn conversion_errors_name_placeholder_and_value() {
+        let a = args("x -1 300", &["count", "size", "byte"]);
+
+        let e = a.get::<usize>("count").err().unwrap();
+        assert_eq!(e.kind(), CmdErrorKind::Argument);
+        assert_eq!(e.message(), "Expected integer for <count>, got 'x'");
+
+        let e = a.get::<u32>("size").err().unwrap();
+        assert_eq!(e.message(), "Expected integer for <size>, got '-1'");
+
+        let e = a.get::<u8>("byte").err().unwrap();
+        assert_eq!(e.message(), "Expected integer for <byte>, got '300'");
+    }
+
+    #[test]
+    fn missing_placeholders() {
+        let a = args("1", &["int"]);
+
+        let e = a.get::<usize>("name").err().unwrap();
+        assert_eq!(e.kind(), CmdErrorKind::Argument);
+        assert_eq!(e.message(), "Missing value for <name>");
+
+        assert_eq!(a.get_opt::<usize>("name").unwrap(), None);
+        assert_eq!(a.get_opt::<usize>("int").unwrap(), Some(1));
+        assert!(a.get_all::<usize>("name").unwrap().is_empty());
+        assert!(a.value("name").is_none());
+    }
+
+    #[test]
+    fn repeated_placeholders() {
+        let a = args("copy a b c", &["cmd", "file", "file", "file"]);
+
+        assert_eq!(a.get_all::<String>("file").unwrap(), ["a", "b", "c"]);
+        // The first value is the one given by get
+        assert_eq!(a.get::<String>("file").unwrap(), "a");
+        assert_eq!(a.values("file").len(), 3);
+
+        let a = args("1 x 3", &["n", "n", "n"]);
+        let e = a.get_all::<usize>("n").err().unwrap();
+        assert_eq!(e.message(), "Expected integer for <n>, got 'x'");
+    }
+
+    #[test]
+    fn get_opt_reports_bad_values() {
+        let a = args("x", &["int"]);
+
+        let e = a.get_opt::<i32>("int").err().unwrap();
+        assert_eq!(e.message(),
+                   "Expected signed integer for <int>, got 'x'");
+    }
+}
@@ -1,16 +1,12 @@
+#![allow(clippy::needless_return)]
+
 extern crate cmdui;
 
-use cmdui::{CmdUI, CmdApp, CommandPart, KeywordExpander};
+use std::path::PathBuf;
+use std::process;
 
-const COMMAND_LIST: &'static [&'static str] = &[
-    "set attr1 <bool>",
-    "set attr2 <int>",
-    "read <filename>",
-    "store <filename>",
-    "add <key> <word>",
-    "run",
-    "help",
-];
+use cmdui::{CmdUI, CmdApp, Args, Builtin, Candidate, CmdError, CmdErrorKind,
+            CommandPart, KeywordExpander, MatchMode, Mode, check_pattern};
 
 struct DemoKeywordExpander {
 }
@@ -20,42 +16,36 @@ impl DemoKeywordExpander {
         Self {}
     }
 
-    fn expand_keys(&self, _: &str) -> Vec<String> {
-        return vec!["akey".to_string(), "bkey".to_string(),
-                    "ckey".to_string()];
-    }
-
-    fn expand_words(&self, _: &str) -> Vec<String> {
-        return vec!["apple".to_string(), "orange".to_string(),
-                    "banana".to_string()];
+    fn expand_words(&self, _: &str) -> Vec<Candidate> {
+        return vec!["apple".into(), "orange".into(), "banana".into()];
     }
 }
 
 impl KeywordExpander for DemoKeywordExpander {
-    fn command_list<'a>(&self) -> &'a [&'a str] {
-        return COMMAND_LIST;
-    }
-
-    fn expand_keyword(&self, cp: &CommandPart, parts: &Vec<String>)
-                      -> Vec<String> {
+    fn expand_candidates(&self, cp: &CommandPart, parts: &Vec<String>)
+                         -> Vec<Candidate> {
         let lpart = &parts[parts.len() - 1];
 
         match cp.as_str() {
-            "<filename>"  => { self.expand_filename(lpart) },
-            "<key>"       => { self.expand_keys(lpart) },
+            "<filename>"  => { self.expand_files(lpart) },
             "<word>"      => { self.expand_words(lpart) },
-            "<bool>"      => { vec!["false".to_string(), "true".to_string()] },
-            s             => { vec![s.to_string()] },
+            _             => { vec![] },
         }
     }
 }
 
 struct DemoApp {
+    interface: String,
+    // Keywords added with the add command, each of which becomes a command
+    keywords: Vec<(String, String)>,
 }
 
 impl DemoApp {
     fn new() -> Self {
-        Self {}
+        Self {
+            interface: String::new(),
+            keywords: vec!(),
+        }
     }
 
     fn set_bool_param(&mut self, key: &str, val: bool) {
@@ -66,70 +56,83 @@ impl DemoApp {
         println!("Setting parameter {} to {}", key, val);
     }
 
-    fn read(&mut self, _: Option<&str>) {
+    fn read(&mut self, args: &Args) -> Result<(), CmdError> {
+        let _filename: Option<PathBuf> = args.get_opt("filename")?;
         println!("Reading something");
+        Ok(())
     }
 
-    fn store(&mut self, _: Option<&str>) {
+    fn store(&mut self, args: &Args) -> Result<(), CmdError> {
+        let _filename: Option<PathBuf> = args.get_opt("filename")?;
         println!("Storing something");
+        Ok(())
     }
 
-    fn add_keyword(&mut self, _: &str, _: &str) {
-        println!("Adding keyword");
+    fn add_keyword(&mut self, args: &Args) -> Result<(), CmdError> {
+        let key: String = args.get("key")?;
+        let word: String = args.get("word")?;
+
+        if let Err(e) = check_pattern(&key) {
+            return Err(CmdError::argument(
+                &format!("Bad keyword '{}': {}", key, e)));
+        }
+
+        println!("Adding keyword {} = {}", key, word);
+        self.keywords.retain(|(k, _)| *k != key);
+        self.keywords.push((key, word));
+        Ok(())
     }
 
-    fn run(&mut self) {
+    fn run(&mut self, _: &Args) -> Result<(), CmdError> {
         println!("Running something");
+        Ok(())
     }
-    
-    fn help(&self) {
-        println!("{}", COMMAND_LIST.into_iter()
-                 .map(|c| c.replace("<bool>", "on/off"))
-                 .collect::<Vec<String>>()
-                 .join("\n")
-        );
+
+    fn set_hostname(&mut self, args: &Args) -> Result<(), CmdError> {
+        let name: String = args.get("name")?;
+        println!("Setting hostname to {}", name);
+        Ok(())
+    }
+
+    fn select_interface(&mut self, args: &Args) -> Result<(), CmdError> {
+        let name: String = args.get("name")?;
+
+        if !name.starts_with("eth") {
+            return Err(CmdError::argument(
+                &format!("No such interface '{}'", name)));
+        }
+
+        self.interface = name;
+        Ok(())
+    }
+
+    fn set_interface_state(&mut self, args: &Args) -> Result<(), CmdError> {
+        let words: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        println!("Setting interface {} {}", self.interface, words.join(" "));
+        Ok(())
     }
 }
 
 impl CmdApp for DemoApp {
-    fn command_list<'a>(&self) -> &'a [&'a str] {
-        return COMMAND_LIST;
-    }
-
-    fn execute_line(&mut self, cmd: &str, args: &Vec<String>)
-                    -> Result<(), String> {
-        match cmd {
-            "set attr1" => {
-                <dyn CmdApp>::expects_num_arguments(args, 1)?;
-                self.set_bool_param("attr1", <dyn CmdApp>::parse_bool(&args[0])?);
-            },
-            "set attr2" => {
-                <dyn CmdApp>::expects_num_arguments(args, 1)?;
-                self.set_int_param("attr2", <dyn CmdApp>::parse_int(&args[0])?);
-            },
-            "read" => {
-                self.read(<dyn CmdApp>::opt_part(args, 0));
-            },
-            "store" => {
-                self.store(<dyn CmdApp>::opt_part(args, 0));
-            },
-            "add" => {
-                <dyn CmdApp>::expects_num_arguments(args, 2)?;
-                self.add_keyword(&args[0], &args[1]);
-            },
-            "run" => {
-                self.run();
-            },
-            "help" => {
-                self.help();
-            },
-            "" => { },
-            _ => {
-                return Err("Bad command".to_string());
-            },
+    // A command for each keyword added, printing its word. Keywords which
+    // aren't valid patterns, such as "[", are left out.
+    fn command_list(&self) -> Vec<String> {
+        return self.keywords.iter()
+            .map(|(k, _)| k.clone())
+            .filter(|k| check_pattern(k).is_ok())
+            .collect();
+    }
+
+    fn execute_line(&mut self, cmd: &str, _args: &Vec<String>)
+                    -> Result<(), CmdError> {
+        for (k, w) in &self.keywords {
+            if k == cmd {
+                println!("{}", w);
+                return Ok(());
+            }
         }
 
-        Ok(())
+        return Err(CmdError::runtime("Bad command"));
     }
 
     fn startup(&mut self) {
@@ -144,6 +147,100 @@ impl CmdApp for DemoApp {
 fn main() {
     let mut app = DemoApp::new();
     let kw_exp = DemoKeywordExpander::new();
-
-    CmdUI::new(&mut app, Some(&kw_exp)).read_commands();
+    let mut ui = CmdUI::new(&mut app, Some(&kw_exp));
+
+    // Keys are completed by an expander registered on the CmdUI, while
+    // <signal> and <bool> use the stock ones
+    ui.expander("<key>", |_| {
+        vec![Candidate::new("akey").with_description("the first key"),
+             Candidate::new("bkey").with_description("the second key"),
+             Candidate::new("ckey").with_description("the third key")]
+    });
+
+    ui.command("set attr1 <bool>", |app, args| {
+        app.set_bool_param("attr1", args.get("bool")?);
+        Ok(())
+    })
+    .command("set mode <mode:(?i)fast|safe|off>", |_, args| {
+        let mode: String = args.get("mode")?;
+        println!("Setting mode to {}", mode);
+        Ok(())
+    })
+    .command("set attr2 <int>", |app, args| {
+        app.set_int_param("attr2", args.get("int")?);
+        Ok(())
+    })
+    .command("set volume <volume:0..=100>", |app, args| {
+        app.set_int_param("volume", args.get("volume")?);
+        Ok(())
+    })
+    .command("set name <name:/[a-z][a-z0-9_]*/>", |_, args| {
+        let name: String = args.get("name")?;
+        println!("Setting name to {}", name);
+        Ok(())
+    })
+    .command("read [<filename>]", DemoApp::read)
+    .command("store [<filename>]", DemoApp::store)
+    .command("import <table:*.csv|*.tsv>", |_, args| {
+        let table: PathBuf = args.get("table")?;
+        println!("Importing {}", table.display());
+        Ok(())
+    })
+    .command("add <key> <word>", DemoApp::add_keyword)
+    .command("run", DemoApp::run)
+    .command("signal <signal>", |_, args| {
+        let signal: String = args.get("signal")?;
+        println!("Sending SIG{}", signal);
+        Ok(())
+    });
+
+    ui.describe("set attr1 <bool>", "Set the first attribute", "")
+        .describe("set attr2 <int>", "Set the second attribute", "")
+        .describe("set mode <mode:(?i)fast|safe|off>", "Set the mode", "")
+        .describe("set volume <volume:0..=100>", "Set the volume", "")
+        .describe("set name <name:/[a-z][a-z0-9_]*/>", "Set the name", "")
+        .describe("read [<filename>]", "Read something from a file",
+                  "Reads from the given file, or from a default file \
+                   if no filename is given.")
+        .describe("store [<filename>]", "Store something to a file",
+                  "Writes to the given file, or to a default file if no \
+                   filename is given.")
+        .describe("import <table:*.csv|*.tsv>", "Import a table", "")
+        .describe("add <key> <word>", "Add a keyword", "")
+        .describe("run", "Run the application", "")
+        .describe("signal <signal>", "Send a signal", "")
+        .describe_placeholder("<filename>", "Path of a file")
+        .describe_placeholder("<key>", "Name of a keyword")
+        .describe_placeholder("<word>", "Word to expand the keyword to");
+
+    // Configuration mode, with a sub-mode for each interface
+    let mut interface = Mode::new("config-if", Some(&kw_exp));
+    interface.on_enter(DemoApp::select_interface)
+        .command("(shutdown|no shutdown)", DemoApp::set_interface_state)
+        .describe("(shutdown|no shutdown)", "Disable or enable the interface",
+                  "");
+
+    let mut config = Mode::new("config", Some(&kw_exp));
+    config.command("hostname <name>", DemoApp::set_hostname)
+        .mode("interface <name>", interface)
+        .describe("hostname <name>", "Set the host name", "")
+        .describe("interface <name>", "Configure an interface", "");
+
+    ui.mode("configure", config)
+        .describe("configure", "Enter configuration mode", "");
+
+    // Words are many, and are completed from any part of the word
+    ui.placeholder_match_mode("<word>", MatchMode::Fuzzy);
+
+    ui.enable_builtin(Builtin::Exit)
+        .enable_builtin(Builtin::Quit)
+        .enable_builtin(Builtin::History)
+        .enable_builtin(Builtin::Source)
+        .enable_builtin(Builtin::Echo)
+        .enable_builtin(Builtin::Clear);
+
+    if let Err(e) = ui.read_commands()
+        && let CmdErrorKind::Fatal(status) = e.kind() {
+        process::exit(status);
+    }
 }
@@ -0,0 +1,78 @@
+use crate::help::HELP_PATTERN;
+
+// Commands built into CmdUI. Help is enabled by default, the others are
+// enabled with CmdUI::enable_builtin. A command of the application by the
+// same name replaces the built-in one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Builtin {
+    // Quit at the top level. In a mode, exit always returns to the parent
+    // mode.
+    Exit,
+    // Quit from any mode
+    Quit,
+    // List the command history of the current mode
+    History,
+    // Execute the commands of a file
+    Source,
+    // Print the arguments
+    Echo,
+    // Clear the screen
+    Clear,
+    // Show help for commands
+    Help,
+}
+
+impl Builtin {
+    // The built-in commands, in the order they are listed by help
+    pub(crate) const ALL: [Builtin; 7] = [
+        Builtin::Exit,
+        Builtin::Quit,
+        Builtin::History,
+        Builtin::Source,
+        Builtin::Echo,
+        Builtin::Clear,
+        Builtin::Help,
+    ];
+
+    pub(crate) fn pattern(self) -> &'static str {
+        return match self {
+            Builtin::Exit => { "exit" },
+            Builtin::Quit => { "quit" },
+            Builtin::History => { "history [<int>]" },
+            Builtin::Source => { "source <filename>" },
+            Builtin::Echo => { "echo [<text>...]" },
+            Builtin::Clear => { "clear" },
+            Builtin::Help => { HELP_PATTERN },
+        }
+    }
+
+    // Summary and description, for the help command
+    pub(crate) fn doc(self) -> (&'static str, &'static str) {
+        return match self {
+            Builtin::Exit | Builtin::Quit => {
+                ("Quit", "")
+            },
+            Builtin::History => {
+                ("Show the command history",
+                 "Lists the commands entered, or the last <int> of them.")
+            },
+            Builtin::Source => {
+                ("Execute commands from a file",
+                 "Executes each line of the file as a command. Empty lines \
+                  and lines starting with # are skipped.")
+            },
+            Builtin::Echo => {
+                ("Print a line of text", "")
+            },
+            Builtin::Clear => {
+                ("Clear the screen", "")
+            },
+            Builtin::Help => {
+                ("Show help",
+                 "Lists all commands, or shows help for the commands \
+                  starting with the given words, or for a placeholder such \
+                  as <int>.")
+            },
+        }
+    }
+}
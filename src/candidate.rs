@@ -0,0 +1,65 @@
+// A completion candidate, with an optional short description. The
+// description is shown next to the word when candidates are listed, e.g.
+//
+//   attr1  -- enable the turbo mode
+//   attr2  -- set the number of workers
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    word: String,
+    description: String,
+}
+
+impl Candidate {
+    pub fn new(word: &str) -> Self {
+        Self {
+            word: word.to_string(),
+            description: String::new(),
+        }
+    }
+
+    pub fn with_description(mut self, description: &str) -> Self {
+        self.description = description.to_string();
+        return self;
+    }
+
+    pub fn word(&self) -> &str {
+        return &self.word;
+    }
+
+    pub fn description(&self) -> &str {
+        return &self.description;
+    }
+}
+
+impl From<String> for Candidate {
+    fn from(word: String) -> Self {
+        Self {
+            word: word,
+            description: String::new(),
+        }
+    }
+}
+
+impl From<&str> for Candidate {
+    fn from(word: &str) -> Self {
+        return Self::new(word);
+    }
+}
+
+// A byte size for display, e.g. "512 B" or "1.5 KiB"
+pub(crate) fn size_text(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit + 1 < units.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        return format!("{} B", bytes);
+    }
+
+    return format!("{:.1} {}", size, units[unit]);
+}
@@ -0,0 +1,633 @@
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+use console::measure_text_width;
+
+pub struct CommandLine {
+    line: String
+}
+
+impl CommandLine {
+    pub fn new(line: String) -> Self {
+        Self {
+            line: line
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        return &self.line;
+    }
+
+    pub fn parts(&self) -> CommandLineIterator<'_> {
+        return CommandLineIterator::new(self);
+    }
+
+    // Split the line into parts, failing on the first syntax error. Unlike
+    // parts(), which is used for completion of a line still being typed,
+    // unterminated quotes are not accepted here, and no empty part is
+    // added for trailing whitespace.
+    pub fn parse(&self) -> Result<Vec<CommandPart<'_>>, ParseError> {
+        let mut parts = vec!();
+
+        for p in self.parts() {
+            if let Some(e) = p.error {
+                return Err(e);
+            }
+
+            if !p.slice.is_empty() {
+                parts.push(p);
+            }
+        }
+
+        return Ok(parts);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    UnterminatedSingleQuote,
+    UnterminatedDoubleQuote,
+    TrailingBackslash,
+}
+
+// A syntax error on the command line. The span is the byte range of the
+// offending text.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    kind: ParseErrorKind,
+    span: Range<usize>,
+}
+
+impl ParseError {
+    fn new(kind: ParseErrorKind, span: Range<usize>) -> Self {
+        Self {
+            kind: kind,
+            span: span,
+        }
+    }
+
+    pub fn kind(&self) -> ParseErrorKind {
+        return self.kind;
+    }
+
+    pub fn span(&self) -> Range<usize> {
+        return self.span.clone();
+    }
+
+    // Render the error with the offending line, and a ^~~~ marker under
+    // the bad region.
+    pub fn diagnostic(&self, line: &str) -> String {
+        let line = line.replace('\t', " ");
+        let indent = measure_text_width(&line[..self.span.start]);
+        let width = measure_text_width(&line[self.span.clone()]).max(1);
+
+        return format!("{}\n{}^{}\n{}.", line, " ".repeat(indent),
+                       "~".repeat(width - 1), self);
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self.kind {
+            ParseErrorKind::UnterminatedSingleQuote => {
+                "Unterminated single quote"
+            },
+            ParseErrorKind::UnterminatedDoubleQuote => {
+                "Unterminated double quote"
+            },
+            ParseErrorKind::TrailingBackslash => {
+                "Backslash at end of line"
+            },
+        };
+
+        return f.write_str(msg);
+    }
+}
+
+impl Error for ParseError {}
+
+// Quote a word so that it is read back as the same word by the tokenizer.
+// Single quotes are preferred. Double quotes are used if the word contains
+// a single quote itself.
+pub fn quote(word: &str) -> String {
+    if word.is_empty() {
+        return "''".to_string();
+    }
+
+    if !word.chars().any(|c| c.is_whitespace() || c == '\'' || c == '"'
+                         || c == '\\') {
+        return word.to_string();
+    }
+
+    if !word.contains('\'') {
+        return format!("'{}'", word);
+    }
+
+    let mut quoted = "\"".to_string();
+
+    for c in word.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+
+    quoted.push('"');
+
+    return quoted;
+}
+
+// A command line argument, as delivered to the application. Holds both the
+// unquoted value and the text as it was typed, along with its position on
+// the command line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Arg {
+    value: String,
+    raw: String,
+    span: Range<usize>,
+    is_quoted: bool,
+}
+
+impl Arg {
+    // The unquoted value of the argument
+    pub fn as_str(&self) -> &str {
+        return &self.value;
+    }
+
+    // The argument exactly as it was typed on the line
+    pub fn raw(&self) -> &str {
+        return &self.raw;
+    }
+
+    // Byte range of the argument on the command line
+    pub fn span(&self) -> Range<usize> {
+        return self.span.clone();
+    }
+
+    // Whether any part of the argument was quoted
+    pub fn is_quoted(&self) -> bool {
+        return self.is_quoted;
+    }
+
+    pub(crate) fn set_value(&mut self, value: &str) {
+        self.value = value.to_string();
+    }
+}
+
+impl AsRef<str> for Arg {
+    fn as_ref(&self) -> &str {
+        return &self.value;
+    }
+}
+
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str(&self.value);
+    }
+}
+
+pub struct CommandPart<'a> {
+    // The part as typed, including quotes and escapes
+    slice: &'a str,
+    // Byte position of the part on the command line
+    start: usize,
+    // The part with quotes and escapes removed
+    value: String,
+    is_quoted: bool,
+    error: Option<ParseError>,
+}
+
+impl<'a> CommandPart<'a> {
+    // A part holding the word as it is, not read from a command line
+    pub(crate) fn new(slice: &'a str) -> Self {
+        Self {
+            slice: slice,
+            start: 0,
+            value: slice.to_string(),
+            is_quoted: false,
+            error: None,
+        }
+    }
+
+    fn parsed(slice: &'a str, start: usize, value: String, is_quoted: bool)
+              -> Self {
+        Self {
+            slice: slice,
+            start: start,
+            value: value,
+            is_quoted: is_quoted,
+            error: None,
+        }
+    }
+
+    fn with_error(mut self, error: ParseError) -> Self {
+        self.error = Some(error);
+        return self;
+    }
+
+    // The unquoted value of the part
+    pub fn as_str(&self) -> &str {
+        return &self.value;
+    }
+
+    // The part exactly as it was typed on the line
+    pub fn raw(&self) -> &'a str {
+        return self.slice;
+    }
+
+    // Whether any part of the word was quoted
+    pub fn is_quoted(&self) -> bool {
+        return self.is_quoted;
+    }
+
+    // Byte range of the part on the command line
+    pub fn span(&self) -> Range<usize> {
+        return self.start..self.start + self.slice.len();
+    }
+
+    // Syntax error in the part, if any
+    pub fn error(&self) -> Option<&ParseError> {
+        return self.error.as_ref();
+    }
+
+    pub(crate) fn to_arg(&self) -> Arg {
+        return Arg {
+            value: self.value.clone(),
+            raw: self.slice.to_string(),
+            span: self.span(),
+            is_quoted: self.is_quoted,
+        };
+    }
+
+    // The quote left open at the end of the part, if any
+    fn open_quote(&self) -> QuoteState {
+        return match self.error.as_ref().map(|e| e.kind()) {
+            Some(ParseErrorKind::UnterminatedSingleQuote) => {
+                QuoteState::Single
+            },
+            Some(ParseErrorKind::UnterminatedDoubleQuote) => {
+                QuoteState::Double
+            },
+            _ => {
+                QuoteState::Unquoted
+            },
+        }
+    }
+
+    // The text replacing the part, as typed so far, to complete it to the
+    // given word. The text already typed is kept, and the rest of the word
+    // is escaped to suit the quote open at the end of the part. If nothing
+    // was quoted or escaped, the whole word is quoted when needed. The
+    // quote is closed if the word is finished, and left open otherwise,
+    // e.g. for a directory to be completed further.
+    pub(crate) fn complete_to(&self, word: &str, finished: bool) -> String {
+        let state = self.open_quote();
+        let rest = word.strip_prefix(self.value.as_str());
+
+        let Some(rest) = rest.filter(|_| self.slice != self.value) else {
+            let mut quoted = quote(word);
+
+            if !finished && quoted != word {
+                quoted.pop();
+            }
+
+            return quoted;
+        };
+
+        let mut ret = self.slice.to_string();
+
+        for c in rest.chars() {
+            match state {
+                QuoteState::Unquoted => {
+                    if c.is_whitespace() || "'\"\\".contains(c) {
+                        ret.push('\\');
+                    }
+                    ret.push(c);
+                },
+                // A single quote can't be escaped within single quotes, so
+                // the quote is closed, an escaped one added and the quote
+                // opened again
+                QuoteState::Single => {
+                    if c == '\'' {
+                        ret.push_str("'\\''");
+                    }
+                    else {
+                        ret.push(c);
+                    }
+                },
+                QuoteState::Double => {
+                    if c == '"' || c == '\\' {
+                        ret.push('\\');
+                    }
+                    ret.push(c);
+                },
+            }
+        }
+
+        if finished {
+            match state {
+                QuoteState::Unquoted => { },
+                QuoteState::Single => { ret.push('\''); },
+                QuoteState::Double => { ret.push('"'); },
+            }
+        }
+
+        return ret;
+    }
+}
+
+impl<'a> PartialEq for CommandPart<'a> {
+    fn eq(&self, other: &CommandPart) -> bool {
+        return self.value == other.value;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum QuoteState {
+    Unquoted,
+    Single,
+    Double,
+}
+
+// Splits a command line into parts. Parts are separated by runs of
+// whitespace, and leading whitespace is ignored. Single
+// quotes keep everything up to the next single quote literally. Double
+// quotes do the same, except that a backslash escapes a following '"' or
+// '\'. Outside quotes, a backslash escapes any character. Quoted and
+// unquoted segments without a space between them form a single part, so
+// that e.g. 'it'"'"s' reads as it's.
+pub struct CommandLineIterator<'a> {
+    line: &'a CommandLine,
+    position: usize,
+}
+
+impl<'a> CommandLineIterator<'a> {
+    fn new(line: &'a CommandLine) -> Self {
+        Self {
+            line: line,
+            position: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        return self.line.as_str().len();
+    }
+}
+
+impl<'a> Iterator for CommandLineIterator<'a> {
+    type Item = CommandPart<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position > self.len() {
+            return None;
+        }
+
+        // Skip whitespace separating this part from the previous one
+        let line = self.line.as_str();
+        let start = line[self.position..]
+            .find(|c: char| !c.is_whitespace())
+            .map_or(self.len(), |p| self.position + p);
+
+        // If we are exactly at the end of line, the line is empty or there
+        // is whitespace after the last part. Add an empty final part to
+        // signify this.
+        if start == self.len() {
+            self.position = self.len() + 1;
+            return Some(CommandPart::parsed(
+                "", start, String::new(), false));
+        }
+
+        let mut chars = line[start..].char_indices()
+            .map(|(i, c)| (start + i, c))
+            .peekable();
+        let mut value = String::new();
+        let mut state = QuoteState::Unquoted;
+        let mut is_quoted = false;
+        // Position of the last opening quote
+        let mut quote_start = start;
+        // Position of the whitespace ending the part, if any
+        let mut end = None;
+
+        while let Some((i, c)) = chars.next() {
+            match state {
+                QuoteState::Unquoted => {
+                    match c {
+                        c if c.is_whitespace() => {
+                            end = Some(i);
+                            break;
+                        },
+                        '\'' => {
+                            state = QuoteState::Single;
+                            is_quoted = true;
+                            quote_start = i;
+                        },
+                        '"' => {
+                            state = QuoteState::Double;
+                            is_quoted = true;
+                            quote_start = i;
+                        },
+                        '\\' => {
+                            if let Some((_, escaped)) = chars.next() {
+                                value.push(escaped);
+                            }
+                            else {
+                                // Backslash at end of line. Nothing to
+                                // escape.
+                                let error = ParseError::new(
+                                    ParseErrorKind::TrailingBackslash,
+                                    i..self.len());
+                                self.position = self.len() + 1;
+                                return Some(CommandPart::parsed(
+                                    &line[start..], start, value, is_quoted)
+                                    .with_error(error));
+                            }
+                        },
+                        _ => {
+                            value.push(c);
+                        },
+                    }
+                },
+                QuoteState::Single => {
+                    if c == '\'' {
+                        state = QuoteState::Unquoted;
+                    }
+                    else {
+                        value.push(c);
+                    }
+                },
+                QuoteState::Double => {
+                    match c {
+                        '"' => {
+                            state = QuoteState::Unquoted;
+                        },
+                        '\\' => {
+                            match chars.peek() {
+                                Some(&(_, e)) if e == '"' || e == '\\' => {
+                                    value.push(e);
+                                    chars.next();
+                                },
+                                _ => {
+                                    value.push(c);
+                                },
+                            }
+                        },
+                        _ => {
+                            value.push(c);
+                        },
+                    }
+                },
+            }
+        }
+
+        if let Some(end) = end {
+            self.position = end;
+            return Some(CommandPart::parsed(
+                &line[start..end], start, value, is_quoted));
+        }
+
+        self.position = self.len() + 1;
+        let part = CommandPart::parsed(
+            &line[start..], start, value, is_quoted);
+
+        // An unterminated quote can only occur at the end of the line. The
+        // rest of the line is then treated as part of the quoted string,
+        // and the error is recorded with the part.
+        let kind = match state {
+            QuoteState::Unquoted => {
+                return Some(part);
+            },
+            QuoteState::Single => {
+                ParseErrorKind::UnterminatedSingleQuote
+            },
+            QuoteState::Double => {
+                ParseErrorKind::UnterminatedDoubleQuote
+            },
+        };
+
+        return Some(part.with_error(
+            ParseError::new(kind, quote_start..self.len())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(line: &str) -> Vec<String> {
+        let cl = CommandLine::new(line.to_string());
+        return cl.parts().map(|p| p.as_str().to_string()).collect();
+    }
+
+    fn last_part_completed(line: &str, word: &str, finished: bool)
+                           -> String {
+        let cl = CommandLine::new(line.to_string());
+        let parts: Vec<CommandPart> = cl.parts().collect();
+        return parts[parts.len() - 1].complete_to(word, finished);
+    }
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(values("set  attr1\ton"), ["set", "attr1", "on"]);
+        assert_eq!(values("  set"), ["set"]);
+    }
+
+    #[test]
+    fn adds_empty_part_after_trailing_whitespace() {
+        assert_eq!(values(""), [""]);
+        assert_eq!(values("set "), ["set", ""]);
+
+        let cl = CommandLine::new("set ".to_string());
+        assert_eq!(cl.parts().last().unwrap().span(), 4..4);
+    }
+
+    #[test]
+    fn removes_quotes_and_escapes() {
+        assert_eq!(values("'a b' \"c d\" e\\ f"), ["a b", "c d", "e f"]);
+        assert_eq!(values("'it'\"'\"s"), ["it's"]);
+        assert_eq!(values(r#""a\"b\\c\d""#), [r#"a"b\c\d"#]);
+        assert_eq!(values(r"'a\b'"), [r"a\b"]);
+    }
+
+    #[test]
+    fn keeps_raw_text_and_span() {
+        let cl = CommandLine::new("open 'my file'".to_string());
+        let parts: Vec<CommandPart> = cl.parts().collect();
+
+        assert_eq!(parts[1].raw(), "'my file'");
+        assert_eq!(parts[1].span(), 5..14);
+        assert!(parts[1].is_quoted());
+        assert!(!parts[0].is_quoted());
+    }
+
+    #[test]
+    fn reports_unterminated_quote() {
+        let cl = CommandLine::new("echo 'abc".to_string());
+        let parts: Vec<CommandPart> = cl.parts().collect();
+        let e = parts[1].error().unwrap();
+
+        assert_eq!(parts[1].as_str(), "abc");
+        assert_eq!(e.kind(), ParseErrorKind::UnterminatedSingleQuote);
+        assert_eq!(e.span(), 5..9);
+
+        let e = cl.parse().err().unwrap();
+        assert_eq!(e.kind(), ParseErrorKind::UnterminatedSingleQuote);
+
+        let cl = CommandLine::new("echo a\"b".to_string());
+        assert_eq!(cl.parse().err().unwrap().kind(),
+                   ParseErrorKind::UnterminatedDoubleQuote);
+    }
+
+    #[test]
+    fn reports_trailing_backslash() {
+        let cl = CommandLine::new("echo a\\".to_string());
+        let e = cl.parse().err().unwrap();
+
+        assert_eq!(e.kind(), ParseErrorKind::TrailingBackslash);
+        assert_eq!(e.span(), 6..7);
+    }
+
+    #[test]
+    fn parse_drops_empty_trailing_part() {
+        let cl = CommandLine::new("set on ".to_string());
+        let parts = cl.parse().unwrap();
+
+        assert_eq!(parts.len(), 2);
+    }
+
+    #[test]
+    fn quote_round_trips() {
+        for word in ["plain", "", "a b", "it's", "say \"hi\"", "a\\b",
+                     "it's \"x\""] {
+            let quoted = quote(word);
+            assert_eq!(values(&quoted), [word], "quoted as {}", quoted);
+        }
+
+        assert_eq!(quote("plain"), "plain");
+        assert_eq!(quote("a b"), "'a b'");
+        assert_eq!(quote("it's"), "\"it's\"");
+    }
+
+    #[test]
+    fn complete_to_quotes_plain_words() {
+        assert_eq!(last_part_completed("open my", "my file", true),
+                   "'my file'");
+        assert_eq!(last_part_completed("open my", "my dir/", false),
+                   "'my dir/");
+        assert_eq!(last_part_completed("open fi", "file", true), "file");
+    }
+
+    #[test]
+    fn complete_to_keeps_typed_quotes() {
+        assert_eq!(last_part_completed("open 'my", "my file", true),
+                   "'my file'");
+        assert_eq!(last_part_completed("open \"my", "my \"x\"", true),
+                   "\"my \\\"x\\\"\"");
+        assert_eq!(last_part_completed("open 'it", "it's", true),
+                   "'it'\\''s'");
+        assert_eq!(last_part_completed("open my\\ ", "my file", true),
+                   "my\\ file");
+        assert_eq!(last_part_completed("open 'my", "my dir/", false),
+                   "'my dir/");
+    }
+}
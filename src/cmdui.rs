@@ -1,73 +1,114 @@
 use rustyline::hint::Hinter;
 use rustyline::Helper;
-use rustyline::{CompletionType, Context, Editor, Config};
+use rustyline::{Changeset, CompletionType, Context, Editor, Config};
 use rustyline::completion::{Completer, Pair};
 use rustyline::validate::{Validator, ValidationResult, ValidationContext};
 use rustyline::highlight::{Highlighter};
 use rustyline::error::ReadlineError;
+use rustyline::history::History;
+use rustyline::line_buffer::LineBuffer;
 extern crate term_size;
 
-use std::collections::HashMap;
-use std::ops::{Range, RangeFrom};
-use console::{Term, Key};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use console::{measure_text_width, Key};
 use std::cmp::min;
 use std::io;
 use std::io::stdin;
 use std::io::Write;
 use std::fs;
+use std::mem;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::args::{Args, FromArg};
+use crate::builtins::Builtin;
+use crate::candidate::Candidate;
+use crate::commands::{check_described, CommandSet};
+use crate::cmdline::{Arg, CommandLine, CommandPart, ParseErrorKind};
+use crate::error::{CmdError, CmdErrorKind};
+use crate::expanders::Expanders;
+use crate::files::{self, FileFilter};
+use crate::grammar::{placeholder_name, Grammar, Term};
+use crate::help::Help;
+use crate::matching::{MatchMode, Matching};
+use crate::mode::{Mode, END_PATTERN};
+use crate::parse;
 
 pub trait KeywordExpander {
-    fn command_list<'a>(&self) -> &'a [&'a str];
-
-    fn expand_keyword(&self, cp: &CommandPart, parts: &Vec<String>)
-                      -> Vec<String>;
-
-    fn expand_filename(&self, path: &str) -> Vec<String> {
-        let mut ret = vec!();
-        let (dir, dpart, fpart);
+    // Not used. Commands are completed from CmdApp::command_list and the
+    // commands registered on CmdUI, the same ones they are dispatched from.
+    #[deprecated(note = "commands are completed from CmdApp::command_list")]
+    fn command_list<'a>(&self) -> &'a [&'a str] {
+        return &[];
+    }
 
-        if let Some(pos) = path.rfind('/') {
-            dir = &path[0..pos + 1];
-            dpart = &path[0..pos + 1];
-            fpart = &path[pos + 1..];
-        }
-        else if path == "." {
-            dir = "./";
-            dpart = ".";
-            fpart = "";
-        }
-        else {
-            dir = "./";
-            dpart = "";
-            fpart = path;
-        }
+    // Candidates for a placeholder, given as cp, e.g. "<filename>". The
+    // parts are the words of the line up to the cursor, the last one being
+    // the word completed.
+    #[allow(clippy::ptr_arg)]
+    fn expand_keyword(&self, _cp: &CommandPart, _parts: &Vec<String>)
+                      -> Vec<String> {
+        return vec!();
+    }
 
-        if let Ok(d) = fs::read_dir(dir) {
-            for entry_result in d {
-                let entry = entry_result.unwrap();
-                if let Some(fstr) = entry.file_name().to_str() {
-                    if fstr.starts_with(fpart) {
-                        let mut fstring = fstr.to_string();
-                        if entry.path().is_dir() {
-                            fstring.push('/');
-                        }
+    // Like expand_keyword, with a description of each candidate, shown
+    // when the candidates are listed. The default implementation gives
+    // the candidates of expand_keyword, without descriptions.
+    #[allow(clippy::ptr_arg)]
+    fn expand_candidates(&self, cp: &CommandPart, parts: &Vec<String>)
+                         -> Vec<Candidate> {
+        return self.expand_keyword(cp, parts).into_iter()
+            .map(Candidate::from)
+            .collect();
+    }
 
-                        ret.push(format!("{}{}", dpart, fstring));
-                    }
-                }
-            }
-        }
+    // The files and directories in the directory of a path, sorted, with a
+    // '/' after directories. They are matched against the path by the
+    // completer. A leading "~" or "$HOME" is expanded, and hidden files are
+    // only offered when the name typed starts with a dot.
+    fn expand_filename(&self, path: &str) -> Vec<String> {
+        return files::complete(path, &FileFilter::default()).into_iter()
+            .map(|c| c.word().to_string())
+            .collect();
+    }
 
-        return ret;
+    // Like expand_filename, with the size of each file as its description
+    fn expand_files(&self, path: &str) -> Vec<Candidate> {
+        return files::complete(path, &FileFilter::default());
     }
 }
 
 pub trait CmdApp {
-    // Mandatory methods
-    fn command_list<'a>(&self) -> &'a [&'a str];
+    // Patterns of the commands handled by execute_line or execute_args.
+    // Commands registered with handlers on CmdUI are not listed here. The
+    // list is used both for dispatch and for completion, and is read again
+    // for every command line, so it may change at runtime.
+    fn command_list(&self) -> Vec<String> {
+        return vec!();
+    }
 
-    fn execute_line(&mut self, cmd: &str, args: &Vec<String>)
-                    -> Result<(), String>;
+    // Command execution. Implement one of these, unless all commands are
+    // registered with handlers on CmdUI.
+
+    // Called with the unquoted argument values of a command line. The
+    // kind of a returned error decides how it is handled, see CmdErrorKind.
+    #[allow(clippy::ptr_arg)]
+    fn execute_line(&mut self, _cmd: &str, _args: &Vec<String>)
+                    -> Result<(), CmdError> {
+        return Err(CmdError::runtime("Bad command"));
+    }
+
+    // Called with the parsed arguments of a command line. Arguments can be
+    // looked up by placeholder name, with type conversion, and carry the
+    // raw text and position of each argument. The default implementation
+    // passes the unquoted argument values on to execute_line.
+    fn execute_args(&mut self, cmd: &str, args: &Args)
+                    -> Result<(), CmdError> {
+        let values = args.iter().map(|a| a.to_string()).collect();
+        return self.execute_line(cmd, &values);
+    }
 
     // Optional callbacks
     fn startup(&mut self) { }
@@ -78,48 +119,65 @@ pub trait CmdApp {
     fn confirm_yes_no(&self) -> bool {
         let mut buf = String::new();
         stdin().read_line(&mut buf).unwrap();
-        return buf.trim().to_lowercase() == "y" || buf.trim().len() == 0;
+        return buf.trim().to_lowercase() == "y" || buf.trim().is_empty();
     }
 
     // Wait-for-keypress, for the pager function.
     fn wait_for_key(&self) -> Key {
-        let term = Term::stdout();
+        let term = console::Term::stdout();
         return term.read_key().unwrap();
     }
 
     // Pager function. Lists words in columns, one page at a time.
     fn print_columns(&self, lines: &[String], max_line: usize) {
-        let (term_w, term_h) = term_size::dimensions().unwrap_or((80, 25));
+        let (term_w, _) = term_size::dimensions().unwrap_or((80, 25));
         let min_padding = 2;
-        let cols = term_w/(max_line + min_padding);
+        let cols = (term_w/(max_line + min_padding)).max(1);
         let cwidth = term_w/cols;
-        let end_row = (lines.len() + cols - 1) / cols;
+
+        let rows: Vec<String> = lines.chunks(cols)
+            .map(|row| {
+                let mut r = String::new();
+
+                for (i, l) in row.iter().enumerate() {
+                    if i + 1 == cols {
+                        r.push_str(l);
+                    }
+                    else {
+                        r.push_str(&format!("{: <1$}", l, cwidth));
+                    }
+                }
+
+                r
+            })
+            .collect();
+
+        self.print_paged(&rows);
+    }
+
+    // Pager function. Prints lines one page at a time, if they don't fit
+    // on the terminal.
+    fn print_paged(&self, lines: &[String]) {
+        let (_, term_h) = term_size::dimensions().unwrap_or((80, 25));
+        let end_row = lines.len();
         let page_size = term_h - 1;
-        let is_paged = end_row > page_size;
+        let is_paged = end_row > page_size && console::Term::stdout().is_term();
         let mut position = 0;
 
         'outer: loop {
-            let lstart = position*cols;
-            let lend = min((position + page_size)*cols, lines.len());
-            let mut i = 0;
+            let lend = if is_paged {
+                min(position + page_size, lines.len())
+            }
+            else {
+                lines.len()
+            };
 
             if is_paged {
                 print!("\r");
             }
 
-            for l in &lines[lstart..lend] {
-                i = (i + 1)%cols;
-
-                if i == 0 {
-                    println!("{}", l);
-                }
-                else {
-                    print!("{: <1$}", l, cwidth);
-                }
-            }
-
-            if i != 0 {
-                println!();
+            for l in &lines[position..lend] {
+                println!("{}", l);
             }
 
             if !is_paged {
@@ -131,17 +189,13 @@ pub trait CmdApp {
 
             loop {
                 match self.wait_for_key() {
-                    Key::Home => {
-                        if position > 0 {
-                            position = 0;
-                            break;
-                        }
+                    Key::Home if position > 0 => {
+                        position = 0;
+                        break;
                     },
-                    Key::End => {
-                        if position + page_size < end_row {
-                            position = end_row - page_size;
-                            break;
-                        }
+                    Key::End if position + page_size < end_row => {
+                        position = end_row - page_size;
+                        break;
                     },
                     Key::PageUp | Key::Char('b') => {
                         if position > page_size {
@@ -163,17 +217,14 @@ pub trait CmdApp {
                             break;
                         }
                     },
-                    Key::ArrowUp => {
-                        if position > 0 {
-                            position -= 1;
-                            break;
-                        }
+                    Key::ArrowUp if position > 0 => {
+                        position -= 1;
+                        break;
                     },
-                    Key::Enter | Key::ArrowDown => {
-                        if position + page_size < end_row {
-                            position += 1;
-                            break;
-                        }
+                    Key::Enter | Key::ArrowDown
+                        if position + page_size < end_row => {
+                        position += 1;
+                        break;
                     },
                     Key::Char('q') | Key::CtrlC | Key::Escape => {
                         break 'outer;
@@ -194,21 +245,26 @@ pub trait CmdApp {
 }
 
 impl dyn CmdApp {
+    // Integers may be written in hex, octal or binary, with a 0x, 0o or 0b
+    // prefix.
     pub fn parse_int(intstr: &str) -> Result<usize, String> {
-        if let Ok(length) = intstr.parse() {
-            return Ok(length);
-        }
-        else {
-            return Err(format!("Expected integer, got '{}'", intstr));
-        }
+        return Self::parse_value(intstr);
+    }
+
+    pub fn parse_signed(intstr: &str) -> Result<i64, String> {
+        return Self::parse_value(intstr);
+    }
+
+    pub fn parse_float(floatstr: &str) -> Result<f64, String> {
+        return Self::parse_value(floatstr);
     }
 
     pub fn parse_bool(boolstr: &str) -> Result<bool, String> {
         return match boolstr {
-            "on" | "true" | "1" => {
+            "on" | "true" | "t" | "1" => {
                 Ok(true)
             },
-            "off" | "false" | "0" => {
+            "off" | "false" | "f" | "0" => {
                 Ok(false)
             },
             _ => {
@@ -217,7 +273,38 @@ impl dyn CmdApp {
         }
     }
 
-    pub fn opt_part(args: &Vec<String>, pos: usize) -> Option<&str> {
+    // A size in bytes, e.g. "4KiB" or "1.5G". K, M, G, T and P are powers
+    // of 1024, with or without a trailing iB. kB, MB, GB, TB and PB are
+    // powers of 1000.
+    pub fn parse_size(sizestr: &str) -> Result<u64, String> {
+        return parse::size(sizestr).ok_or_else(|| {
+            format!("Expected byte size, got '{}'", sizestr)
+        });
+    }
+
+    // A duration, e.g. "250ms", "2h" or "1h30m". The units are ns, us, ms,
+    // s, m, h and d. A number without a unit is seconds.
+    pub fn parse_duration(durstr: &str) -> Result<Duration, String> {
+        return Self::parse_value(durstr);
+    }
+
+    // Any type implementing FromArg
+    pub fn parse_value<T: FromArg>(value: &str) -> Result<T, String> {
+        return T::from_arg(value).ok_or_else(|| {
+            format!("Expected {}, got '{}'", T::EXPECTED, value)
+        });
+    }
+
+    // Any type implementing FromStr. The expected kind of value is named
+    // in the error message.
+    pub fn parse<T: FromStr>(value: &str, expected: &str)
+                             -> Result<T, String> {
+        return value.parse().map_err(|_| {
+            format!("Expected {}, got '{}'", expected, value)
+        });
+    }
+
+    pub fn opt_part(args: &[String], pos: usize) -> Option<&str> {
         if args.len() > pos {
             Some(&args[pos])
         }
@@ -226,7 +313,7 @@ impl dyn CmdApp {
         }
     }
 
-    pub fn expects_num_arguments(args: &Vec<String>, n: usize)
+    pub fn expects_num_arguments(args: &[String], n: usize)
                              -> Result<(), String> {
         if args.len() < n {
             return Err(format!("Expected {} arguments", n));
@@ -237,308 +324,290 @@ impl dyn CmdApp {
     }
 }
 
-struct CommandLine {
-    line: String
+#[derive(Helper)]
+struct CommandHelper<'a> {
+    completer: CommandCompleter<'a>,
 }
 
-impl CommandLine {
-    fn new(line: String) -> Self {
-        Self {
-            line: line
-        }
-    }
-
-    fn as_str(&self) -> &str {
-        return &self.line;
-    }
-
-    fn parts(&self) -> CommandLineIterator {
-        return CommandLineIterator::new(self);
-    }
+struct CommandCompleter<'a> {
+    opt_kw_exp: Option<&'a dyn KeywordExpander>,
+    grammar: Grammar,
+    // Index of the built-in help command in the grammar
+    help: Option<usize>,
+    matching: Matching,
+    expanders: Expanders,
 }
 
-pub struct CommandPart<'a> {
-    slice: &'a str,
-    is_quoted: bool,
-    is_error: bool,
+// A completion candidate matching the word being completed
+struct Completion {
+    replacement: String,
+    description: String,
+    rank: (usize, usize),
 }
 
-impl<'a> CommandPart<'a> {
-    fn new(slice: &'a str) -> Self {
-        let is_quoted = slice.find(' ').is_some();
+impl<'a> CommandCompleter<'a> {
+    fn new(opt_kw_exp: Option<&'a dyn KeywordExpander>, grammar: Grammar,
+           help: Option<usize>, matching: Matching, expanders: Expanders)
+           -> Self {
         Self {
-            slice: slice,
-            is_quoted: is_quoted,
-            is_error: false,
+            opt_kw_exp: opt_kw_exp,
+            grammar: grammar,
+            help: help,
+            matching: matching,
+            expanders: expanders,
         }
     }
 
-    fn error(slice: &'a str) -> Self {
-        Self {
-            slice: slice,
-            is_quoted: false,
-            is_error: true,
+    // End of the word starting at start, when the cursor at pos is inside
+    // it
+    fn word_end(line: &str, start: usize, pos: usize) -> usize {
+        if start >= pos {
+            return pos;
         }
-    }
 
-    pub fn as_str(&self) -> &str {
-        return self.slice;
-    }
-    
-    fn starts_with(&self, other: &CommandPart) -> bool {
-        return self.slice.starts_with(other.slice);
-    }
-
-    fn to_string(&self) -> String {
-        if self.is_error {
-            return format!("Bad_command: {}", self.slice);
-        }
-        else if self.is_quoted {
-            return format!("'{}'", self.slice);
-        }
-        else {
-            return self.slice.to_string();
-        }
-    }
-}
+        let line_cl = CommandLine::new(line.to_string());
 
-impl<'a> PartialEq for CommandPart<'a> {
-    fn eq(&self, other: &CommandPart) -> bool {
-        return self.slice == other.slice;
+        return line_cl.parts()
+            .find(|w| w.span().start == start)
+            .map_or(pos, |w| w.span().end.max(pos));
     }
-}
 
-pub struct CommandLineIterator<'a> {
-    line: &'a CommandLine,
-    position: usize,
-}
-
-impl<'a> CommandLineIterator<'a> {
-    fn new(line: &'a CommandLine) -> Self {
-        Self {
-            line: line,
-            position: 0,
+    // End of the text replaced by a completion of the word starting at
+    // start. The rest of the word after the cursor is left alone, unless
+    // the completion already ends with it, as for "Cargo.toml" with the
+    // cursor after "Carg" in "Cargo.toml".
+    fn replaced_end(line: &str, start: usize, pos: usize, completion: &str)
+                    -> usize {
+        let end = Self::word_end(line, start, pos);
+        let tail = &line[pos..end];
+        let word = completion.strip_suffix(' ').unwrap_or(completion);
+
+        if word.ends_with(tail) || completion.ends_with(tail) {
+            return end;
         }
-    }
 
-    fn slice(&self, r: Range<usize>) -> &'a str {
-        return &self.line.as_str()[r];
+        return pos;
     }
 
-    fn slice_from(&self, r: RangeFrom<usize>) -> &'a str {
-        return &self.line.as_str()[r];
-    }
+    // Complete the word at the cursor. Only the text before the cursor is
+    // matched, so that a word in the middle of the line can be completed
+    // without touching the rest of the line. The returned position is the
+    // start of the word, and each replacement is the completed word alone,
+    // which replaces the text up to the cursor (see replaced_end).
+    fn complete(&self, full_line: &str, pos: usize, _ctx: &Context)
+        -> rustyline::Result<(usize, Vec<Pair>)>
+    {
+        let mut pairs = HashMap::new();
+        let (line, _) = full_line.split_at(pos);
 
-    fn find_from(&self, r: RangeFrom<usize>, c: char) -> Option<usize> {
-        let start = r.start;
-        if let Some(nextpos) = &self.slice_from(r).find(c) {
-            return Some(start + nextpos);
-        }
-        else {
-            return None;
-        }
-    }
+        let line_cl = CommandLine::new(line.to_string());
+        let lwords: Vec<CommandPart> = line_cl.parts().collect();
 
-    fn find_from_to(&self, r: Range<usize>, c: char) -> Option<usize> {
-        let start = r.start;
-        if let Some(nextpos) = &self.slice(r).find(c) {
-            return Some(start + nextpos);
-        }
-        else {
-            return None;
+        // Return empty completion list if line has errors. An unterminated
+        // quote is fine, the user may still be typing the quoted word.
+        for w in &lwords {
+            if let Some(e) = w.error()
+                && e.kind() == ParseErrorKind::TrailingBackslash {
+                return Ok((pos, vec!()));
+            }
         }
-    }
 
-    fn len(&self) -> usize {
-        return self.line.as_str().len();
-    }
+        // The last part is the unfinished one being completed, up to the
+        // cursor. The finished parts are kept as they are.
+        let Some((lpart, finished)) = lwords.split_last() else {
+            return Ok((pos, vec!()));
+        };
+
+        // Candidates are listed without the directory part already typed,
+        // e.g. "demoapp.rs" rather than "src/bin/demoapp.rs"
+        let dir_len = lpart.as_str().rfind('/').map_or(0, |i| i + 1);
+
+        let words: Vec<&str> = finished.iter().map(|w| w.as_str()).collect();
+        let parts: Vec<String> = lwords.iter()
+            .map(|w| w.as_str().to_string())
+            .collect();
+
+        // Loop over all terms which may follow the finished parts, in any
+        // command
+        for (index, term, more) in self.grammar.next_terms(&words) {
+            let (mode, ignore_case) = match term {
+                Term::Placeholder(p) if Some(index) != self.help => {
+                    (self.matching.mode(Some(p.name())), p.ignores_case())
+                },
+                _ => {
+                    (self.matching.mode(None), false)
+                },
+            };
 
-    fn char_is(&self, pos: usize, c: char) -> bool {
-        return self.line.as_str()[pos..].starts_with(c);
-    }
-}
+            let keys = match term {
+                Term::Keyword(k) => {
+                    vec![Candidate::new(k)]
+                },
+                // Help topics are the keywords of the commands
+                Term::Placeholder(_) if Some(index) == self.help => {
+                    self.grammar.next_terms(&words[1..]).iter()
+                        .filter_map(|(_, t, _)| match t {
+                            Term::Keyword(k) => { Some(Candidate::new(k)) },
+                            Term::Placeholder(_) => { None },
+                        })
+                        .collect()
+                },
+                // Enumerations are completed from the pattern
+                Term::Placeholder(p) if p.is_enumeration() => {
+                    p.values().into_iter().map(Candidate::new).collect()
+                },
+                // Placeholders with globs are completed from the files
+                // matching them
+                Term::Placeholder(p) if p.file_filter().is_some() => {
+                    let filter = p.file_filter().unwrap_or_default();
+                    files::complete(lpart.as_str(), &filter)
+                },
+                // Ranges and regular expressions have no candidates, only
+                // a hint
+                Term::Placeholder(p) if p.constraint().is_some() => {
+                    continue;
+                },
+                // Registered and stock expanders come before the keyword
+                // expander of the application
+                Term::Placeholder(p) => {
+                    if let Some(keys) = self.expanders.expand(p.name(),
+                                                              lpart.as_str()) {
+                        keys
+                    }
+                    else if let Some(kw_exp) = self.opt_kw_exp {
+                        let text = p.text();
+                        kw_exp.expand_candidates(&CommandPart::new(&text),
+                                                 &parts)
+                    }
+                    else {
+                        continue;
+                    }
+                },
+            };
 
-impl<'a> Iterator for CommandLineIterator<'a> {
-    type Item = CommandPart<'a>;
+            for c in &keys {
+                let k = c.word();
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let pos = self.position;
+                // Unfinished (last) part. Accept partial match.
+                let Some(rank) = self.matching.rank(mode, lpart.as_str(), k,
+                                                    ignore_case)
+                else {
+                    continue;
+                };
 
-        if pos > self.len() {
-            return None;
-        }
+                // A directory is not finished, as the user will likely go
+                // on with a file in it
+                let finished = !k.ends_with('/');
+                let mut replacement = lpart.complete_to(k, finished);
 
-        // If we are exactly at the end of line, a space was added after
-        // the last part. Add an empty final part to signify this.
-        if pos == self.len() {
-            self.position += 1;
-            return Some(CommandPart::new(""));
-        }
+                // No space is added if anything follows the completed
+                // word, be it a space or the text kept after the cursor
+                let end = Self::replaced_end(full_line, lpart.span().start,
+                                             pos, &replacement);
 
-        if self.char_is(pos, '\'') {
-            // Quoted part
-            if let Some(nextpos) = &self.find_from(pos + 1.., '\'') {
-                // Located second quote
-                if nextpos + 1 != self.len() {
-                    // Not end of line
-                    if self.char_is(nextpos + 1, ' ') {
-                        // Space after quote
-                        self.position = nextpos + 2;
-                    }
-                    else {
-                        // No space found after quote. Treat as error
-                        self.position = nextpos + 1;
-                        return Some(CommandPart::error(
-                            &self.slice(pos..*nextpos)));
-                    }
+                if more && finished && full_line[end..].is_empty() {
+                    replacement.push(' ');
                 }
-                else {
-                    // End of line
-                    self.position = nextpos + 2;
+
+                // Fuzzy or substring matches may not start with the
+                // directory typed, and are shown whole
+                let display = k.strip_prefix(&lpart.as_str()[..dir_len])
+                    .filter(|d| !d.is_empty())
+                    .unwrap_or(k)
+                    .to_string();
+
+                // A candidate found more than once keeps a description,
+                // and its best rank
+                let entry = pairs.entry(display).or_insert(Completion {
+                    replacement: replacement,
+                    description: String::new(),
+                    rank: rank,
+                });
+
+                if entry.description.is_empty() {
+                    entry.description = c.description().to_string();
                 }
 
-                return Some(CommandPart::new(&self.slice(pos + 1..*nextpos)));
-            }
-            else {
-                // No second quote found. Treat the rest of the string as part.
-                self.position = self.len() + 1;
-                return Some(CommandPart::new(&self.slice_from(pos + 1..)));
+                entry.rank = entry.rank.min(rank);
             }
         }
-        else {
-            // Unquoted part
-            if let Some(nextpos) = &self.find_from(pos.., ' ') {
-                // Not end of line
-                // Check that part doesn't contain a quote
-                self.position = nextpos + 1;
-                if let Some(_) = self.find_from_to(pos..*nextpos, '\'') {
-                    return Some(CommandPart::error(&self.slice(pos..*nextpos)));
-                }
-                else {
-                    return Some(CommandPart::new(&self.slice(pos..*nextpos)));
-                }
-            }
-            else {
-                // End of line.
-                self.position = self.len() + 1;
-                // Check that part doesn't contain a quote
-                if let Some(_) = self.find_from(pos.., '\'') {
-                    return Some(CommandPart::error(&self.slice_from(pos..)));
+
+        // Descriptions are lined up in a column after the longest word
+        let width = pairs.keys()
+            .map(|d| measure_text_width(d))
+            .max()
+            .unwrap_or(0);
+
+        // Best matches first, then in alphabetical order
+        let mut completions: Vec<(String, Completion)> =
+            pairs.into_iter().collect();
+        completions.sort_by(|(a, ca), (b, cb)| {
+            ca.rank.cmp(&cb.rank).then_with(|| a.cmp(b))
+        });
+
+        let pairvec: Vec<Pair> = completions.into_iter()
+            .map(|(display, c)| {
+                let display = if c.description.is_empty() {
+                    display
                 }
                 else {
-                    return Some(CommandPart::new(&self.slice_from(pos..)));
-                }
-            }
-        }
-    }
-}
-
-#[derive(Helper)]
-struct CommandHelper<'a> {
-    completer: CommandCompleter<'a>,
-}
+                    let pad = width - measure_text_width(&display);
+                    format!("{}{}  -- {}", display, " ".repeat(pad),
+                            c.description)
+                };
 
-struct CommandCompleter<'a> {
-    kw_exp: &'a dyn KeywordExpander,
-}
+                Pair {
+                    display: display,
+                    replacement: c.replacement,
+                }
+            })
+            .collect();
 
-impl<'a> CommandCompleter<'a> {
-    fn new(kw_exp: &'a dyn KeywordExpander) -> Self {
-        Self {
-            kw_exp: kw_exp,
-        }
+        Ok((lpart.span().start, pairvec))
     }
 
-    fn complete(&self, line: &str, _pos: usize, _ctx: &Context)
-        -> rustyline::Result<(usize, Vec<Pair>)>
-    {
-        let mut pairs = HashMap::new();
+    // Hint at the range or regular expression of the placeholder being
+    // typed, e.g. "<volume:0..=100>" after "set volume ", or " (0..=100)"
+    // once the value is being typed. Placeholders without a constraint get
+    // the hint registered for them, if any, as for <int>.
+    fn hint(&self, line: &str, pos: usize) -> Option<String> {
+        if pos < line.len() {
+            return None;
+        }
 
         let line_cl = CommandLine::new(line.to_string());
         let lwords: Vec<CommandPart> = line_cl.parts().collect();
 
-        // Return empty completion list if line has errors
-        for w in &lwords {
-            if w.is_error {
-                return Ok((0, vec!()));
-            }
+        if lwords.iter().any(|w| w.error().is_some()) {
+            return None;
         }
 
-        // Loop over all commands
-        'commands: for cmd in self.kw_exp.command_list() {
-            let mut prefix = "".to_string();
-
-            let cmd_cl = CommandLine::new(cmd.to_string());
-            let cmd_vec: Vec<CommandPart> = cmd_cl.parts().collect();
-            let cmd_vec_len = cmd_vec.len();
-            let mut parts = vec!();
+        let (lpart, finished) = lwords.split_last()?;
+        let words: Vec<&str> = finished.iter().map(|w| w.as_str()).collect();
 
-            // Loop over command parts
-            'parts: for (i, cp) in cmd_vec.into_iter().enumerate() {
-                if i == lwords.len() {
-                    continue 'commands;
+        for (_, term, _) in self.grammar.next_terms(&words) {
+            if let Term::Placeholder(p) = term
+                && let Some(c) = p.constraint() {
+                if lpart.as_str().is_empty() {
+                    return Some(format!("<{}:{}>", p.name(), c));
                 }
-
-                let lpart = &lwords[i];
-
-                parts.push(lpart.to_string());
-                let keys = self.kw_exp.expand_keyword(&cp, &parts);
-
-                let mut got_matches = false;
-
-                for k in keys.iter().map(|k| CommandPart::new(&k)) {
-                    if i == lwords.len() - 1 {
-                        // Unfinished (last) part. Accept partial match.
-                        if !k.starts_with(&lpart) {
-                            continue;
-                        }
-                    }
-                    else {
-                        // Not last part. Require complete match for keywords
-                        // No check for variable parameters
-                        if !cp.slice.starts_with('<') {
-                            if *lpart != k {
-                                continue;
-                            }
-                        }
-
-                        // The line matches the complete part. Add it to
-                        // prefix, skip to next command part, continue
-                        // matching.
-                        prefix.push_str(&lpart.to_string());
-                        prefix.push_str(" ");
-
-                        continue 'parts;
-                    }
-
-                    // All line parts match the corresponding part in a
-                    // command. Create a replacement pair.
-                    let mut replacement = prefix.clone();
-                    replacement.push_str(&k.to_string());
-
-                    if cmd_vec_len > i + 1 {
-                        replacement.push(' ');
-                    }
-
-                    let display = k.to_string();
-
-                    pairs.insert(display.clone(), Pair {
-                        display: display,
-                        replacement: replacement,
-                    });
-                    got_matches = true;
+                else {
+                    return Some(format!(" ({})", c));
                 }
+            }
 
-                if !got_matches {
-                    continue 'commands;
+            if let Term::Placeholder(p) = term
+                && let Some(h) = self.expanders.hint_for(p.name()) {
+                if lpart.as_str().is_empty() {
+                    return Some(format!("{}  {}", p.text(), h));
+                }
+                else {
+                    return Some(format!("  ({})", h));
                 }
             }
         }
 
-        let mut pairvec: Vec<Pair> = pairs.into_values().collect();
-        pairvec.sort_by(|a, b| a.display.cmp(&b.display));
-
-        Ok((0, pairvec))
+        return None;
     }
 }
 
@@ -550,15 +619,24 @@ impl<'a> Completer for CommandHelper<'a> {
     {
         self.completer.complete(line, pos, ctx)
     }
+
+    // Replace the word up to the cursor, or up to its end if the
+    // completion ends with the rest of the word
+    fn update(&self, line: &mut LineBuffer, start: usize, elected: &str,
+              cl: &mut Changeset) {
+        let end = CommandCompleter::replaced_end(line.as_str(), start,
+                                                 line.pos(), elected);
+        line.replace(start..end, elected, cl);
+    }
 }
 
 impl<'a> Hinter for CommandHelper<'a> {
     type Hint = String;
 
-    fn hint(&self, _line: &str, _pos: usize, _ctx: &Context)
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context)
             -> Option<String>
     {
-        None
+        self.completer.hint(line, pos)
     }
 }
 
@@ -570,26 +648,439 @@ impl<'a> Validator for CommandHelper<'a> {
     }
 }
 
-impl<'a> Highlighter for CommandHelper<'a> {}
+impl<'a> Highlighter for CommandHelper<'a> {
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(console::style(hint).dim().to_string())
+    }
+}
 
-pub struct CmdUI<'a> {
-    app: &'a mut dyn CmdApp,
-    opt_kw_exp: Option<&'a dyn KeywordExpander>,
+// What a pattern of the grammar is dispatched to
+enum Target {
+    // Registered command, by index in the command set of the mode
+    Command(usize),
+    // Sub-mode, by index in the mode
+    Mode(usize),
+    // Command of CmdApp::command_list, handled by the application
+    App,
+    // Leave all modes
+    End,
+    Builtin(Builtin),
+}
+
+// Limit on nested source commands, which would otherwise recurse forever
+// on a file sourcing itself
+const MAX_SOURCE_DEPTH: usize = 16;
+
+// True if a command starting with the same word as the built-in command
+// is defined
+fn is_defined(patterns: &[String], builtin: &str) -> bool {
+    let word = builtin.split_whitespace().next();
+    return patterns.iter().any(|p| p.split_whitespace().next() == word);
+}
+
+pub struct CmdUI<'a, A: CmdApp + ?Sized = dyn CmdApp> {
+    app: &'a mut A,
+    root: Mode<'a, A>,
+    builtins: HashSet<Builtin>,
+    // Path of sub-mode indices from the top level to the current mode
+    path: Vec<usize>,
+    quit: bool,
+    source_depth: usize,
+    matching: Matching,
+    expanders: Expanders,
+    // Patterns which didn't compile, and have been reported
+    bad_patterns: HashSet<String>,
 }
 
-impl<'a> CmdUI<'a> {
+impl<'a, A: CmdApp + ?Sized> CmdUI<'a, A> {
     pub fn new(
-        app: &'a mut dyn CmdApp,
+        app: &'a mut A,
         opt_kw_exp: Option<&'a dyn KeywordExpander>,
     ) -> Self
     {
         Self {
             app: app,
-            opt_kw_exp: opt_kw_exp,
+            root: Mode::new("", opt_kw_exp),
+            builtins: HashSet::from([Builtin::Help]),
+            path: vec!(),
+            quit: false,
+            source_depth: 0,
+            matching: Matching::default(),
+            expanders: Expanders::stock(),
+            bad_patterns: HashSet::new(),
+        }
+    }
+
+    // Register a command with its handler. Registered commands are
+    // dispatched, completed and listed from the pattern alone, without
+    // going through CmdApp::execute_line. Panics if the pattern is not
+    // valid.
+    #[track_caller]
+    pub fn command<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where F: FnMut(&mut A, &Args) -> Result<(), CmdError> + 'static
+    {
+        self.root.command(pattern, handler);
+        return self;
+    }
+
+    // Register all commands of a command set
+    pub fn add_commands(&mut self, commands: CommandSet<A>) -> &mut Self {
+        self.root.add_commands(commands);
+        return self;
+    }
+
+    // Register a command mode, entered with the given command pattern.
+    // Panics if the pattern is not valid.
+    #[track_caller]
+    pub fn mode(&mut self, pattern: &str, mode: Mode<'a, A>) -> &mut Self {
+        self.root.mode(pattern, mode);
+        return self;
+    }
+
+    // Attach help texts to a command, shown by the help command. See
+    // CommandSet::describe. The command is either registered, or listed by
+    // CmdApp::command_list, and this panics if it is neither.
+    #[track_caller]
+    pub fn describe(&mut self, pattern: &str, summary: &str,
+                    description: &str) -> &mut Self {
+        let listed = self.app.command_list();
+
+        check_described(pattern, self.root.commands().patterns()
+                        .chain(self.root.mode_patterns())
+                        .chain(listed.iter().map(|p| p.as_str())));
+        self.root.commands_mut().insert_doc(pattern, summary, description);
+        return self;
+    }
+
+    // Attach a help text to a placeholder, e.g. "<filename>"
+    pub fn describe_placeholder(&mut self, placeholder: &str,
+                                description: &str) -> &mut Self {
+        self.root.describe_placeholder(placeholder, description);
+        return self;
+    }
+
+    // Enable a built-in command, in all modes
+    pub fn enable_builtin(&mut self, builtin: Builtin) -> &mut Self {
+        self.builtins.insert(builtin);
+        return self;
+    }
+
+    // Hide a built-in command
+    pub fn disable_builtin(&mut self, builtin: Builtin) -> &mut Self {
+        self.builtins.remove(&builtin);
+        return self;
+    }
+
+    // Set how words are matched when completing. Prefix matching is the
+    // default.
+    pub fn match_mode(&mut self, mode: MatchMode) -> &mut Self {
+        self.matching.mode = mode;
+        return self;
+    }
+
+    // Set how values of a placeholder, e.g. "<key>", are matched when
+    // completing
+    pub fn placeholder_match_mode(&mut self, placeholder: &str,
+                                  mode: MatchMode) -> &mut Self {
+        let name = placeholder_name(placeholder);
+        self.matching.placeholders.insert(name.to_string(), mode);
+        return self;
+    }
+
+    // Register a completer for a placeholder, e.g. "<key>". It is given
+    // the word being completed, and returns the candidates. It replaces any
+    // stock expander by the same name, and is used in all modes, before
+    // the keyword expander of the mode.
+    pub fn expander<F>(&mut self, placeholder: &str, expand: F) -> &mut Self
+    where F: Fn(&str) -> Vec<Candidate> + 'static
+    {
+        let name = placeholder_name(placeholder);
+        self.expanders.insert(name, Rc::new(expand));
+        return self;
+    }
+
+    // Set the hint shown while a value of a placeholder is typed
+    pub fn placeholder_hint(&mut self, placeholder: &str, hint: &str)
+                            -> &mut Self {
+        let name = placeholder_name(placeholder);
+        self.expanders.hint(name, hint);
+        return self;
+    }
+
+    pub fn commands(&self) -> &CommandSet<A> {
+        return self.root.commands();
+    }
+
+    // Patterns of the current mode, along with what each of them is
+    // dispatched to. The registered commands come first, then the entries
+    // of sub-modes, the command list of the application (at the top level
+    // only) and the built-in commands. A built-in command is left out if
+    // the mode has a command of its own by the same name. Both dispatch
+    // and completion use these patterns.
+    fn patterns(&self) -> (Vec<String>, Vec<Target>) {
+        let mode = self.root.descendant(&self.path);
+        let mut patterns = vec!();
+        let mut targets = vec!();
+
+        for (i, p) in mode.commands().patterns().enumerate() {
+            patterns.push(p.to_string());
+            targets.push(Target::Command(i));
+        }
+
+        for (i, p) in mode.mode_patterns().enumerate() {
+            patterns.push(p.to_string());
+            targets.push(Target::Mode(i));
+        }
+
+        if self.path.is_empty() {
+            for p in self.app.command_list() {
+                patterns.push(p);
+                targets.push(Target::App);
+            }
+        }
+
+        let in_mode = !self.path.is_empty();
+        let mut builtins = vec!();
+
+        for b in Builtin::ALL {
+            if self.builtins.contains(&b) || (in_mode && b == Builtin::Exit) {
+                builtins.push((b.pattern(), Target::Builtin(b)));
+            }
+
+            if in_mode && b == Builtin::Exit {
+                builtins.push((END_PATTERN, Target::End));
+            }
+        }
+
+        for (pattern, target) in builtins {
+            if !is_defined(&patterns, pattern) {
+                patterns.push(pattern.to_string());
+                targets.push(target);
+            }
+        }
+
+        return (patterns, targets);
+    }
+
+    // The grammar of the current mode, along with the patterns and targets
+    // of the commands in it. Patterns which don't compile, e.g. ones built
+    // at runtime by CmdApp::command_list, are left out, and reported the
+    // first time they are seen.
+    fn grammar(&mut self) -> (Grammar, Vec<String>, Vec<Target>) {
+        let (mut patterns, mut targets) = self.patterns();
+        let (grammar, bad) = Grammar::new(&patterns);
+
+        for (i, e) in bad.into_iter().rev() {
+            let pattern = patterns.remove(i);
+            targets.remove(i);
+
+            if self.bad_patterns.insert(pattern.clone()) {
+                println!("Bad command pattern '{}': {}", pattern, e);
+            }
+        }
+
+        return (grammar, patterns, targets);
+    }
+
+    fn print_help(&self, grammar: &Grammar, patterns: &[String],
+                  targets: &[Target], topic: &[&str]) {
+        let (term_w, _) = term_size::dimensions().unwrap_or((80, 25));
+        let in_mode = !self.path.is_empty();
+        let builtin_docs: Vec<Option<(&str, &str)>> = targets.iter()
+            .map(|t| match t {
+                Target::End => {
+                    Some(("Leave all modes", "Returns to the top level."))
+                },
+                Target::Builtin(Builtin::Exit) if in_mode => {
+                    Some(("Leave the mode", "Returns to the parent mode."))
+                },
+                Target::Builtin(b) => {
+                    Some(b.doc())
+                },
+                _ => {
+                    None
+                },
+            })
+            .collect();
+        let help = Help {
+            grammar: grammar,
+            patterns: patterns,
+            builtin_docs: &builtin_docs,
+            commands: self.root.descendant(&self.path).commands(),
+            width: term_w,
+        };
+
+        match help.lines(topic) {
+            Ok(lines) => {
+                self.app.print_paged(&lines);
+            },
+            Err(e) => {
+                println!("{}", e);
+            },
+        }
+    }
+
+    fn print_history(&self, count: Option<usize>) {
+        let history = self.root.descendant(&self.path).history();
+        let skip = history.len().saturating_sub(count.unwrap_or(usize::MAX));
+
+        for (i, line) in history.iter().enumerate().skip(skip) {
+            println!("{:5}  {}", i + 1, line);
+        }
+    }
+
+    // Execute the commands of a file, one per line. Errors are printed
+    // with the file name and line number, and the script goes on, unless
+    // the error aborts it.
+    fn source(&mut self, filename: &str) -> Result<(), CmdError> {
+        if self.source_depth >= MAX_SOURCE_DEPTH {
+            return Err(CmdError::abort("Too many nested source commands"));
         }
+
+        let contents = fs::read_to_string(filename).map_err(|e| {
+            CmdError::runtime(&format!("Cannot read '{}': {}", filename, e))
+                .with_source(e)
+        })?;
+
+        self.source_depth += 1;
+        let mut result = Ok(());
+
+        for (i, line) in contents.lines().enumerate() {
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+
+            if let Err(e) = self.execute(line) {
+                let e = e.context(&format!("{}:{}", filename, i + 1));
+
+                match e.kind() {
+                    CmdErrorKind::Abort | CmdErrorKind::Fatal(_) => {
+                        result = Err(e);
+                        break;
+                    },
+                    _ => {
+                        println!("{}", e);
+                    },
+                }
+            }
+
+            if self.quit {
+                break;
+            }
+        }
+
+        self.source_depth -= 1;
+
+        return result;
     }
 
-    pub fn read_commands(&mut self) {
+    fn prompt(&self) -> String {
+        if self.path.is_empty() {
+            return "> ".to_string();
+        }
+
+        return format!("({})> ", self.root.descendant(&self.path).name());
+    }
+
+    // Execute a command line in the current mode. Usage errors of the
+    // command get the usage of the command appended.
+    fn execute(&mut self, line: &str) -> Result<(), CmdError> {
+        let cl = CommandLine::new(line.to_string());
+        let args: Vec<Arg> = match cl.parse() {
+            Ok(parts) => {
+                parts.iter().map(|p| p.to_arg()).collect()
+            },
+            Err(e) => {
+                let msg = e.diagnostic(cl.as_str());
+                return Err(CmdError::usage(&msg).with_source(e));
+            },
+        };
+
+        if args.is_empty() {
+            return Ok(());
+        }
+
+        // Match the line against the command patterns. The leading
+        // keywords of the matching pattern make up the command, the rest
+        // of the line is passed on as arguments.
+        let (grammar, patterns, targets) = self.grammar();
+
+        let d = grammar.dispatch(&args).map_err(|e| CmdError::usage(&e))?;
+        let (index, cmd, args) = (d.index(), d.command(), d.args(&args));
+
+        // Recently used values are ranked first when completing
+        for a in args.iter() {
+            self.matching.remember(a.as_str());
+        }
+
+        let result = match targets[index] {
+            Target::Command(i) => {
+                self.root.descendant_mut(&self.path).commands_mut()
+                    .get_mut(i).call(self.app, &args)
+            },
+            Target::Mode(i) => {
+                let mut path = self.path.clone();
+                path.push(i);
+                self.root.descendant_mut(&path).enter(self.app, &args)?;
+                self.path = path;
+                Ok(())
+            },
+            Target::App => {
+                self.app.execute_args(&cmd, &args)
+            },
+            Target::End => {
+                self.path.clear();
+                Ok(())
+            },
+            Target::Builtin(Builtin::Exit) => {
+                if self.path.pop().is_none() {
+                    self.quit = true;
+                }
+                Ok(())
+            },
+            Target::Builtin(Builtin::Quit) => {
+                self.quit = true;
+                Ok(())
+            },
+            Target::Builtin(Builtin::History) => {
+                self.print_history(args.get_opt("int")?);
+                Ok(())
+            },
+            Target::Builtin(Builtin::Source) => {
+                let filename: String = args.get("filename")?;
+                self.source(&filename)
+            },
+            Target::Builtin(Builtin::Echo) => {
+                let words: Vec<&str> = args.iter()
+                    .map(|a| a.as_str())
+                    .collect();
+                println!("{}", words.join(" "));
+                Ok(())
+            },
+            Target::Builtin(Builtin::Clear) => {
+                console::Term::stdout().clear_screen().map_err(|e| {
+                    CmdError::runtime(&e.to_string()).with_source(e)
+                })
+            },
+            Target::Builtin(Builtin::Help) => {
+                let topic: Vec<&str> = args.iter()
+                    .map(|a| a.as_str())
+                    .collect();
+                self.print_help(&grammar, &patterns, &targets, &topic);
+                Ok(())
+            },
+        };
+
+        return result.map_err(|e| match e.kind() {
+            CmdErrorKind::Usage => { e.with_usage(&patterns[index]) },
+            _ => { e },
+        });
+    }
+
+    // Read and execute commands until the user quits, or a command returns
+    // a fatal error. The fatal error is printed and returned, so that the
+    // application can exit with its status.
+    pub fn read_commands(&mut self) -> Result<(), CmdError> {
         self.app.startup();
 
         let config = Config::builder()
@@ -598,24 +1089,48 @@ impl<'a> CmdUI<'a> {
 
         let mut editor = Editor::with_config(config).unwrap();
 
-        loop {
-            if let Some(kw_exp) = self.opt_kw_exp {
-                let helper = CommandHelper {
-                    completer: CommandCompleter::new(kw_exp),
-                };
-                editor.set_helper(Some(helper));
+        self.path.clear();
+        self.quit = false;
+        let mut result = Ok(());
+
+        while !self.quit {
+            let opt_kw_exp = self.root.descendant(&self.path)
+                .keyword_expander();
+            let (grammar, _, targets) = self.grammar();
+            let help = targets.iter()
+                .position(|t| matches!(t, Target::Builtin(Builtin::Help)));
+            let helper = CommandHelper {
+                completer: CommandCompleter::new(opt_kw_exp,
+                                                 grammar,
+                                                 help,
+                                                 self.matching.clone(),
+                                                 self.expanders.clone()),
+            };
+            editor.set_helper(Some(helper));
+
+            // Each mode has a history of its own, which is swapped into the
+            // editor while reading a line
+            let prompt = self.prompt();
+            let history = self.root.descendant_mut(&self.path).history_mut();
+            mem::swap(editor.history_mut(), history);
+            let readline = editor.readline(&prompt);
+
+            if let Ok(line) = &readline {
+                let _ = editor.add_history_entry(line);
             }
 
-            let mut args: Vec<String>;
-            let readline = editor.readline("> ");
+            mem::swap(editor.history_mut(), history);
 
             match readline {
                 Ok(line) => {
-                    let _ = editor.add_history_entry(&line);
-                    args = CommandLine::new(line)
-                        .parts()
-                        .map(|p| p.to_string())
-                        .collect();
+                    if let Err(e) = self.execute(&line) {
+                        println!("{}", e);
+
+                        if let CmdErrorKind::Fatal(_) = e.kind() {
+                            result = Err(e);
+                            break;
+                        }
+                    }
                 },
                 Err(ReadlineError::Interrupted) => {
                     continue;
@@ -625,64 +1140,92 @@ impl<'a> CmdUI<'a> {
                 },
                 Err(err) => {
                     println!("Error: {:?}", err);
+                    result = Err(CmdError::fatal(&err.to_string(), 1)
+                                 .with_source(err));
                     break;
                 },
             }
+        }
 
-            // Move the left hand static command keywords out of the args
-            // list, and concatenate them into a command string.
-            let mut cmd = "".to_string();
-            let mut cmdlist: Vec<&str> = self.app.command_list().to_vec();
+        self.app.exit();
 
-            loop {
-                if args.len() == 0 {
-                    break;
-                }
+        return result;
+    }
+}
 
-                if args[0].starts_with('<') && args[0].ends_with('>') {
-                    // Next param is a '<keyword>' replacement word, literate.
-                    // Don't include it into the command.
-                    break;
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustyline::history::DefaultHistory;
 
-                // Skip empty args
-                if args[0].is_empty() {
-                    args.remove(0);
-                    continue;
-                }
+    fn completer(patterns: &[&str]) -> CommandCompleter<'static> {
+        let (grammar, _) = Grammar::new(patterns);
+        return CommandCompleter::new(None, grammar, None, Matching::default(),
+                                     Expanders::stock());
+    }
 
-                let p = if cmd.len() == 0 {
-                    args[0].clone()
-                }
-                else {
-                    format!("{} {}", cmd, args[0])
-                };
+    // Complete at the position of the '|' in the line, giving the start and
+    // end of the replaced text, and the replacements
+    fn complete(c: &CommandCompleter, line: &str)
+                -> Vec<(usize, usize, String)> {
+        let pos = line.find('|').unwrap();
+        let line = line.replace('|', "");
+        let history = DefaultHistory::new();
+        let (start, pairs) = c.complete(&line, pos, &Context::new(&history))
+            .unwrap();
+
+        return pairs.into_iter()
+            .map(|p| {
+                let end = CommandCompleter::replaced_end(&line, start, pos,
+                                                         &p.replacement);
+                (start, end, p.replacement)
+            })
+            .collect();
+    }
 
-                cmdlist = cmdlist.into_iter()
-                    .filter(|c| c.starts_with(&p))
-                    .collect();
+    #[test]
+    fn completes_files_in_match_mode() {
+        let mut c = completer(&["store <file>"]);
+        c.matching.placeholders.insert("file".to_string(), MatchMode::Fuzzy);
 
-                if cmdlist.len() > 0 {
-                    cmd = p;
-                    args.remove(0);
-                }
-                else {
-                    break;
-                }
-            }
+        assert_eq!(complete(&c, "store src/bin/dmo|"),
+                   [(6, 17, "src/bin/demoapp.rs".to_string())]);
+    }
 
-            if cmd == "" {
-                if args.len() > 0 {
-                    println!("Bad command.");
-                }
-                continue;
-            }
+    #[test]
+    fn completes_enumerations_ignoring_case() {
+        let c = completer(&["mode <m:(?i)Fast|Slow>", "kind <k:Fast|Slow>"]);
 
-            if let Err(e) = self.app.execute_line(&cmd, &args) {
-                println!("{}", e);
-            }
-        }
+        assert_eq!(complete(&c, "mode f|"), [(5, 6, "Fast".to_string())]);
+        assert_eq!(complete(&c, "kind f|"), []);
+    }
 
-        self.app.exit();
+    #[test]
+    fn word_end_finds_end_of_word_at_cursor() {
+        assert_eq!(CommandCompleter::word_end("store abc x", 6, 8), 9);
+        assert_eq!(CommandCompleter::word_end("store 'a b' x", 6, 8), 11);
+        assert_eq!(CommandCompleter::word_end("store abc", 6, 9), 9);
+        // Nothing typed before the cursor: the word after it is not touched
+        assert_eq!(CommandCompleter::word_end("store x", 6, 6), 6);
+    }
+
+    #[test]
+    fn completes_at_end_of_line() {
+        let c = completer(&["store <x>", "stop"]);
+
+        assert_eq!(complete(&c, "sto|"), [(0, 3, "stop".to_string()),
+                                          (0, 3, "store ".to_string())]);
+    }
+
+    #[test]
+    fn keeps_text_after_cursor() {
+        let c = completer(&["store <x>", "stop"]);
+
+        // "stop" ends with the "op" after the cursor, and replaces it.
+        // "store" doesn't, and the "op" is kept, without a space.
+        assert_eq!(complete(&c, "st|op x"), [(0, 4, "stop".to_string()),
+                                             (0, 2, "store".to_string())]);
+        assert_eq!(complete(&c, "st|o x"), [(0, 2, "stop".to_string()),
+                                            (0, 2, "store".to_string())]);
     }
 }
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use crate::args::Args;
+use crate::error::CmdError;
+use crate::grammar::{check_pattern, placeholder_name};
+
+pub(crate) type Handler<A> =
+    Box<dyn FnMut(&mut A, &Args) -> Result<(), CmdError>>;
+
+pub(crate) struct Command<A: ?Sized> {
+    pattern: String,
+    handler: Handler<A>,
+}
+
+impl<A: ?Sized> Command<A> {
+    pub(crate) fn pattern(&self) -> &str {
+        return &self.pattern;
+    }
+
+    pub(crate) fn call(&mut self, app: &mut A, args: &Args)
+                       -> Result<(), CmdError> {
+        return (self.handler)(app, args);
+    }
+}
+
+// Check a pattern registered by the application, panicking at the caller
+// if it is not valid, so that a mistyped pattern is caught where it is
+// registered rather than when commands are first read
+#[track_caller]
+pub(crate) fn check_registered(pattern: &str) {
+    if let Err(e) = check_pattern(pattern) {
+        panic!("Bad command pattern '{}': {}", pattern, e);
+    }
+}
+
+// Check that help texts are attached to a known command pattern, panicking
+// at the caller if not, so that a mistyped pattern doesn't silently lose
+// its help texts
+#[track_caller]
+pub(crate) fn check_described<'p, I>(pattern: &str, mut known: I)
+where I: Iterator<Item = &'p str>
+{
+    if !known.any(|p| p == pattern) {
+        panic!("Help texts for unknown command pattern '{}'", pattern);
+    }
+}
+
+// Help texts of a command, shown by the help command
+pub(crate) struct CommandDoc {
+    pub(crate) summary: String,
+    pub(crate) description: String,
+}
+
+// A set of commands, each registered as a pattern along with the handler
+// which executes it. The handler is a closure or a method taking the
+// application and the arguments of the command line:
+//
+//   set.command("set attr2 <int>", |app, args| {
+//       app.set_attr2(args.get("int")?);
+//       Ok(())
+//   });
+//
+// The set also holds the help texts of commands and placeholders.
+pub struct CommandSet<A: ?Sized> {
+    commands: Vec<Command<A>>,
+    docs: HashMap<String, CommandDoc>,
+    placeholder_docs: HashMap<String, String>,
+}
+
+impl<A: ?Sized> CommandSet<A> {
+    pub fn new() -> Self {
+        Self {
+            commands: vec!(),
+            docs: HashMap::new(),
+            placeholder_docs: HashMap::new(),
+        }
+    }
+
+    // Register a command with its handler. Panics if the pattern is not
+    // valid.
+    #[track_caller]
+    pub fn command<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where F: FnMut(&mut A, &Args) -> Result<(), CmdError> + 'static
+    {
+        check_registered(pattern);
+
+        self.commands.push(Command {
+            pattern: pattern.to_string(),
+            handler: Box::new(handler),
+        });
+
+        return self;
+    }
+
+    // Attach help texts to a command pattern. The summary is shown in the
+    // command listing, the description with help for the command alone.
+    // Panics if the pattern is not registered in this set.
+    #[track_caller]
+    pub fn describe(&mut self, pattern: &str, summary: &str,
+                    description: &str) -> &mut Self {
+        check_described(pattern, self.patterns());
+        self.insert_doc(pattern, summary, description);
+        return self;
+    }
+
+    // Attach help texts to a pattern known to be registered
+    pub(crate) fn insert_doc(&mut self, pattern: &str, summary: &str,
+                             description: &str) {
+        self.docs.insert(pattern.to_string(), CommandDoc {
+            summary: summary.to_string(),
+            description: description.to_string(),
+        });
+    }
+
+    // Attach a help text to a placeholder, e.g. "<filename>"
+    pub fn describe_placeholder(&mut self, placeholder: &str,
+                                description: &str) -> &mut Self {
+        let name = placeholder_name(placeholder);
+        self.placeholder_docs.insert(name.to_string(),
+                                     description.to_string());
+
+        return self;
+    }
+
+    // Add all commands and help texts of another set to this one
+    pub fn extend(&mut self, other: CommandSet<A>) -> &mut Self {
+        self.commands.extend(other.commands);
+        self.docs.extend(other.docs);
+        self.placeholder_docs.extend(other.placeholder_docs);
+        return self;
+    }
+
+    pub fn patterns(&self) -> impl Iterator<Item = &str> {
+        return self.commands.iter().map(|c| c.pattern());
+    }
+
+    pub fn len(&self) -> usize {
+        return self.commands.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.commands.is_empty();
+    }
+
+    pub(crate) fn get_mut(&mut self, i: usize) -> &mut Command<A> {
+        return &mut self.commands[i];
+    }
+
+    pub(crate) fn doc(&self, pattern: &str) -> Option<&CommandDoc> {
+        return self.docs.get(pattern);
+    }
+
+    pub(crate) fn placeholder_doc(&self, name: &str) -> Option<&str> {
+        return self.placeholder_docs.get(name).map(|d| d.as_str());
+    }
+}
+
+impl<A: ?Sized> Default for CommandSet<A> {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_registered_command() {
+        let mut commands: CommandSet<()> = CommandSet::new();
+        commands.command("set name <name:/[a-z]+/>", |_, _| Ok(()))
+            .describe("set name <name:/[a-z]+/>", "Set the name", "");
+
+        let doc = commands.doc("set name <name:/[a-z]+/>").unwrap();
+        assert_eq!(doc.summary, "Set the name");
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown command pattern 'set name <name>'")]
+    fn describe_panics_on_unknown_pattern() {
+        let mut commands: CommandSet<()> = CommandSet::new();
+        commands.command("set name <name:/[a-z]+/>", |_, _| Ok(()))
+            .describe("set name <name>", "Set the name", "");
+    }
+
+    #[test]
+    #[should_panic(expected = "Bad command pattern 'set <name'")]
+    fn command_panics_on_bad_pattern() {
+        let mut commands: CommandSet<()> = CommandSet::new();
+        commands.command("set <name", |_, _| Ok(()));
+    }
+}
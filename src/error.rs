@@ -0,0 +1,130 @@
+use std::error::Error;
+use std::fmt;
+
+// How read_commands handles an error returned by a command
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CmdErrorKind {
+    // The command was used wrongly. The error is printed along with the
+    // usage of the command.
+    Usage,
+    // An argument has a bad value. The error is printed.
+    Argument,
+    // The command failed. The error is printed.
+    Runtime,
+    // The error is printed, and a script run with source is stopped.
+    Abort,
+    // The error is printed, and read_commands returns it. The status is
+    // meant as the exit status of the process.
+    Fatal(i32),
+}
+
+// Error returned by command handlers and CmdApp::execute_line. A plain
+// string converts into a runtime error, so that handlers can use ? on
+// functions returning Result<_, String>:
+//
+//   let count: usize = args.get("count")?;
+//   let data = fs::read(path)
+//       .map_err(|e| CmdError::runtime("Cannot read file").with_source(e))?;
+#[derive(Debug)]
+pub struct CmdError {
+    kind: CmdErrorKind,
+    message: String,
+    source: Option<Box<dyn Error + Send + Sync>>,
+}
+
+impl CmdError {
+    pub fn new(kind: CmdErrorKind, message: &str) -> Self {
+        Self {
+            kind: kind,
+            message: message.to_string(),
+            source: None,
+        }
+    }
+
+    pub fn usage(message: &str) -> Self {
+        return Self::new(CmdErrorKind::Usage, message);
+    }
+
+    pub fn argument(message: &str) -> Self {
+        return Self::new(CmdErrorKind::Argument, message);
+    }
+
+    pub fn runtime(message: &str) -> Self {
+        return Self::new(CmdErrorKind::Runtime, message);
+    }
+
+    pub fn abort(message: &str) -> Self {
+        return Self::new(CmdErrorKind::Abort, message);
+    }
+
+    pub fn fatal(message: &str, status: i32) -> Self {
+        return Self::new(CmdErrorKind::Fatal(status), message);
+    }
+
+    // Keep the underlying error, as returned by Error::source
+    pub fn with_source<E>(mut self, source: E) -> Self
+    where E: Error + Send + Sync + 'static
+    {
+        self.source = Some(Box::new(source));
+        return self;
+    }
+
+    pub fn kind(&self) -> CmdErrorKind {
+        return self.kind;
+    }
+
+    pub fn message(&self) -> &str {
+        return &self.message;
+    }
+
+    pub(crate) fn with_usage(mut self, pattern: &str) -> Self {
+        self.message = format!("{}\nUsage: {}", self.message, pattern);
+        return self;
+    }
+
+    // The same error, with the message prefixed by its origin, e.g. the
+    // file and line of a script. The original error is kept as source.
+    pub(crate) fn context(self, prefix: &str) -> Self {
+        Self {
+            kind: self.kind,
+            message: format!("{}: {}", prefix, self.message),
+            source: Some(Box::new(self)),
+        }
+    }
+}
+
+impl fmt::Display for CmdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str(&self.message);
+    }
+}
+
+impl Error for CmdError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        return self.source.as_ref()
+            .map(|s| s.as_ref() as &(dyn Error + 'static));
+    }
+}
+
+impl From<String> for CmdError {
+    fn from(message: String) -> Self {
+        Self {
+            kind: CmdErrorKind::Runtime,
+            message: message,
+            source: None,
+        }
+    }
+}
+
+impl From<&str> for CmdError {
+    fn from(message: &str) -> Self {
+        return Self::runtime(message);
+    }
+}
+
+// For applications still returning Result<_, String>
+impl From<CmdError> for String {
+    fn from(e: CmdError) -> Self {
+        return e.message;
+    }
+}
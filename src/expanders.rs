@@ -0,0 +1,184 @@
+// Stock completers for common placeholders, and the ones registered by
+// the application, keyed by placeholder name.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::rc::Rc;
+
+use crate::candidate::Candidate;
+use crate::files::{self, FileFilter, FileKind};
+
+// A completer for a placeholder. It gets the word being completed, and
+// returns the candidates, which are then matched against the word.
+pub(crate) type ExpandFn = Rc<dyn Fn(&str) -> Vec<Candidate>>;
+
+// Signals, as given to kill -s, with their numbers on Linux
+const SIGNALS: [(&str, u32, &str); 21] = [
+    ("HUP", 1, "hangup"),
+    ("INT", 2, "interrupt"),
+    ("QUIT", 3, "quit"),
+    ("ILL", 4, "illegal instruction"),
+    ("TRAP", 5, "trace trap"),
+    ("ABRT", 6, "abort"),
+    ("BUS", 7, "bus error"),
+    ("FPE", 8, "floating point exception"),
+    ("KILL", 9, "kill"),
+    ("USR1", 10, "user signal 1"),
+    ("SEGV", 11, "segmentation fault"),
+    ("USR2", 12, "user signal 2"),
+    ("PIPE", 13, "broken pipe"),
+    ("ALRM", 14, "alarm clock"),
+    ("TERM", 15, "termination"),
+    ("CHLD", 17, "child stopped or exited"),
+    ("CONT", 18, "continue"),
+    ("STOP", 19, "stop"),
+    ("TSTP", 20, "terminal stop"),
+    ("TTIN", 21, "terminal input"),
+    ("TTOU", 22, "terminal output"),
+];
+
+// Limit on the length of environment variable values shown as
+// descriptions
+const MAX_VALUE_LEN: usize = 40;
+
+// The expanders of a CmdUI, along with hints shown while a value of the
+// placeholder is typed
+#[derive(Clone)]
+pub(crate) struct Expanders {
+    expanders: HashMap<String, ExpandFn>,
+    hints: HashMap<String, String>,
+}
+
+impl Expanders {
+    // The stock expanders: <bool>, <file>, <dir>, <exe>, <env>, <user>,
+    // <group>, <hostname> and <signal>. <int> has a hint, and no
+    // candidates.
+    pub(crate) fn stock() -> Self {
+        let mut ret = Self {
+            expanders: HashMap::new(),
+            hints: HashMap::new(),
+        };
+
+        ret.insert("bool", Rc::new(|_| {
+            ["on", "off", "true", "false"].into_iter()
+                .map(Candidate::new)
+                .collect()
+        }));
+        ret.insert("file", Rc::new(|word| {
+            files::complete(word, &FileFilter::default())
+        }));
+        ret.insert("dir", Rc::new(|word| {
+            files::complete(word, &FileFilter::new(FileKind::Dir))
+        }));
+        ret.insert("exe", Rc::new(|word| {
+            files::complete(word, &FileFilter::new(FileKind::Exe))
+        }));
+        ret.insert("env", Rc::new(|_| env_vars()));
+        ret.insert("user", Rc::new(|_| users()));
+        ret.insert("group", Rc::new(|_| groups()));
+        ret.insert("hostname", Rc::new(|_| hostnames()));
+        ret.insert("signal", Rc::new(|_| {
+            SIGNALS.iter()
+                .map(|(name, number, description)| {
+                    Candidate::new(name).with_description(
+                        &format!("{} ({})", description, number))
+                })
+                .collect()
+        }));
+        ret.insert("int", Rc::new(|_| vec![]));
+        ret.hint("int", "an integer, e.g. 42 or 0x2a");
+
+        return ret;
+    }
+
+    // Add an expander, replacing any stock one by the same name
+    pub(crate) fn insert(&mut self, name: &str, expand: ExpandFn) {
+        self.expanders.insert(name.to_string(), expand);
+    }
+
+    pub(crate) fn hint(&mut self, name: &str, hint: &str) {
+        self.hints.insert(name.to_string(), hint.to_string());
+    }
+
+    // The candidates for a placeholder, or None if it has no expander
+    pub(crate) fn expand(&self, name: &str, word: &str)
+                         -> Option<Vec<Candidate>> {
+        return self.expanders.get(name).map(|e| e(word));
+    }
+
+    pub(crate) fn hint_for(&self, name: &str) -> Option<&str> {
+        return self.hints.get(name).map(|h| h.as_str());
+    }
+}
+
+// Environment variable names, with their values as descriptions
+fn env_vars() -> Vec<Candidate> {
+    return env::vars()
+        .map(|(name, value)| {
+            let value = if value.chars().count() > MAX_VALUE_LEN {
+                let start: String = value.chars()
+                    .take(MAX_VALUE_LEN)
+                    .collect();
+                format!("{}...", start)
+            }
+            else {
+                value
+            };
+
+            Candidate::new(&name).with_description(&value)
+        })
+        .collect();
+}
+
+// The fields of the lines of a colon separated file, such as /etc/passwd,
+// skipping comments
+fn colon_fields(path: &str) -> Vec<Vec<String>> {
+    let contents = fs::read_to_string(path).unwrap_or_default();
+
+    return contents.lines()
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| l.split(':').map(|f| f.to_string()).collect())
+        .collect();
+}
+
+// User names from /etc/passwd, with their full names as descriptions
+fn users() -> Vec<Candidate> {
+    return colon_fields("/etc/passwd").into_iter()
+        .map(|f| {
+            // The full name is the first of the comma separated fields
+            let gecos = f.get(4).map_or("", |g| g.as_str());
+            let full_name = gecos.split(',').next().unwrap_or("");
+
+            Candidate::new(&f[0]).with_description(full_name)
+        })
+        .collect();
+}
+
+// Group names from /etc/group
+fn groups() -> Vec<Candidate> {
+    return colon_fields("/etc/group").into_iter()
+        .map(|f| Candidate::new(&f[0]))
+        .collect();
+}
+
+// Host names from /etc/hosts, with their addresses as descriptions
+fn hostnames() -> Vec<Candidate> {
+    let contents = fs::read_to_string("/etc/hosts").unwrap_or_default();
+    let mut ret = vec!();
+
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut fields = line.split_whitespace();
+
+        let Some(address) = fields.next() else {
+            continue;
+        };
+
+        for name in fields {
+            ret.push(Candidate::new(name).with_description(address));
+        }
+    }
+
+    return ret;
+}
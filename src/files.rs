@@ -0,0 +1,336 @@
+// Completion of file names.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::candidate::{self, Candidate};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum FileKind {
+    Any,
+    Dir,
+    Exe,
+}
+
+// Which files are offered when completing a path. Directories are always
+// offered, so that the user can get to the files in them. If only
+// directories are wanted, the globs apply to them.
+#[derive(Clone, Debug)]
+pub(crate) struct FileFilter {
+    kind: FileKind,
+    // Glob patterns, one of which file names must match, e.g. "*.csv"
+    globs: Vec<String>,
+}
+
+impl FileFilter {
+    pub(crate) fn new(kind: FileKind) -> Self {
+        Self {
+            kind: kind,
+            globs: vec!(),
+        }
+    }
+
+    pub(crate) fn with_globs(mut self, globs: &[String]) -> Self {
+        self.globs = globs.to_vec();
+        return self;
+    }
+
+    fn accepts(&self, name: &str, path: &Path, is_dir: bool) -> bool {
+        if is_dir != (self.kind == FileKind::Dir) {
+            return is_dir;
+        }
+
+        if self.kind == FileKind::Exe && !is_executable(path) {
+            return false;
+        }
+
+        return self.globs.is_empty()
+            || self.globs.iter().any(|g| glob_match(g, name));
+    }
+}
+
+impl Default for FileFilter {
+    fn default() -> Self {
+        return Self::new(FileKind::Any);
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    return fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false);
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    return path.is_file();
+}
+
+// Match a file name against a glob pattern, where '*' matches any run of
+// characters and '?' any single character
+pub(crate) fn glob_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    let (mut pi, mut ni) = (0, 0);
+    // Position of the last '*', and of the name where it was tried
+    let mut star: Option<(usize, usize)> = None;
+
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        }
+        else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ni));
+            pi += 1;
+        }
+        else if let Some((sp, sn)) = star {
+            // Let the last '*' match one more character
+            pi = sp + 1;
+            ni = sn + 1;
+            star = Some((sp, sn + 1));
+        }
+        else {
+            return false;
+        }
+    }
+
+    return p[pi..].iter().all(|c| *c == '*');
+}
+
+// Expand a leading "~" or environment variable, as in "~/src" or
+// "$HOME/src", for reading the directory. Other paths are kept as they
+// are.
+fn expand_home(dir: &str) -> PathBuf {
+    if dir == "~" || dir.starts_with("~/") {
+        if let Some(home) = env::var_os("HOME") {
+            return PathBuf::from(home).join(dir[1..].trim_start_matches('/'));
+        }
+    }
+    else if let Some(var) = dir.strip_prefix('$') {
+        let (name, rest) = var.split_once('/').unwrap_or((var, ""));
+
+        if let Some(value) = env::var_os(name) {
+            return PathBuf::from(value).join(rest);
+        }
+    }
+
+    return PathBuf::from(dir);
+}
+
+// Whether a path is a bare "~", or the name of a set environment variable,
+// as in "$HOME"
+fn is_home(path: &str) -> bool {
+    return path == "~"
+        || path.strip_prefix('$').is_some_and(|name| {
+            !name.is_empty() && !name.contains('/')
+                && env::var_os(name).is_some()
+        });
+}
+
+// The files and directories in the directory of a path, sorted by name,
+// with a '/' after directories and the size of files as their description.
+// All names are listed, whatever the name typed, and are left for the
+// completer to match, so that substring and fuzzy matching work for files
+// too. The directory part is kept as typed, so "~/" is completed to e.g.
+// "~/src/". Hidden files are only offered when the name typed starts with
+// a dot, and entries which can't be read are skipped.
+pub(crate) fn complete(path: &str, filter: &FileFilter) -> Vec<Candidate> {
+    // A bare "~" or "$VAR" naming a directory is completed into, as if
+    // followed by a '/'
+    if is_home(path) {
+        return complete(&format!("{}/", path), filter);
+    }
+
+    // An executable without a directory is looked up in the PATH
+    if filter.kind == FileKind::Exe && !path.contains('/') {
+        return complete_command();
+    }
+
+    let (dpart, fpart) = match path.rfind('/') {
+        Some(pos) => { (&path[..pos + 1], &path[pos + 1..]) },
+        None => { ("", path) },
+    };
+
+    let dir = if dpart.is_empty() {
+        PathBuf::from(".")
+    }
+    else {
+        expand_home(dpart)
+    };
+
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return vec!();
+    };
+
+    let mut ret: Vec<Candidate> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+
+            if name.starts_with('.') && !fpart.starts_with('.') {
+                return None;
+            }
+
+            // Follow symbolic links to tell whether they lead to a
+            // directory
+            let path = entry.path();
+            let metadata = fs::metadata(&path).ok();
+            let is_dir = metadata.as_ref().is_some_and(|m| m.is_dir());
+
+            if !filter.accepts(&name, &path, is_dir) {
+                return None;
+            }
+
+            if is_dir {
+                return Some(Candidate::new(&format!("{}{}/", dpart, name)));
+            }
+
+            let size = metadata.map(|m| candidate::size_text(m.len()))
+                .unwrap_or_default();
+
+            return Some(Candidate::new(&format!("{}{}", dpart, name))
+                        .with_description(&size));
+        })
+        .collect();
+
+    ret.sort_by(|a, b| a.word().cmp(b.word()));
+
+    return ret;
+}
+
+// The executables in the directories of the PATH, left for the completer to
+// match against the name typed
+fn complete_command() -> Vec<Candidate> {
+    let Some(paths) = env::var_os("PATH") else {
+        return vec!();
+    };
+
+    let mut ret: Vec<Candidate> = env::split_paths(&paths)
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.filter_map(|e| e.ok()))
+        .filter_map(|entry| {
+            let file = entry.file_name().into_string().ok()?;
+
+            if !is_executable(&entry.path()) {
+                return None;
+            }
+
+            return Some(Candidate::new(&file));
+        })
+        .collect();
+
+    ret.sort_by(|a, b| a.word().cmp(b.word()));
+    ret.dedup();
+
+    return ret;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A directory of test files, removed when dropped
+    struct TestDir {
+        path: PathBuf,
+    }
+
+    impl TestDir {
+        fn new(name: &str, files: &[&str]) -> Self {
+            let path = env::temp_dir()
+                .join(format!("cmdui-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+
+            for f in files {
+                match f.strip_suffix('/') {
+                    Some(d) => { fs::create_dir(path.join(d)).unwrap(); },
+                    None => { fs::write(path.join(f), "x").unwrap(); },
+                }
+            }
+
+            return Self { path: path };
+        }
+
+        fn words(&self, typed: &str) -> Vec<String> {
+            let path = format!("{}/{}", self.path.display(), typed);
+            let prefix = format!("{}/", self.path.display());
+
+            return complete(&path, &FileFilter::default()).iter()
+                .map(|c| c.word().strip_prefix(&prefix).unwrap().to_string())
+                .collect();
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+
+    #[test]
+    fn glob_match_wildcards() {
+        assert!(glob_match("*.csv", "data.csv"));
+        assert!(glob_match("*.csv", ".csv"));
+        assert!(!glob_match("*.csv", "data.tsv"));
+        assert!(!glob_match("*.csv", "data.csv.bak"));
+        assert!(glob_match("data?.csv", "data1.csv"));
+        assert!(!glob_match("data?.csv", "data.csv"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("a*b*c", "aXbYbZ"));
+        assert!(glob_match("plain", "plain"));
+        assert!(!glob_match("plain", "plainer"));
+    }
+
+    #[test]
+    fn expand_home_expands_leading_home_and_variables() {
+        let home = PathBuf::from(env::var_os("HOME").unwrap());
+        let manifest = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+
+        assert_eq!(expand_home("~"), home);
+        assert_eq!(expand_home("~/src/"), home.join("src/"));
+        assert_eq!(expand_home("$CARGO_MANIFEST_DIR/src"),
+                   manifest.join("src"));
+        assert_eq!(expand_home("$CMDUI_NOT_SET/src"),
+                   PathBuf::from("$CMDUI_NOT_SET/src"));
+        assert_eq!(expand_home("src/~"), PathBuf::from("src/~"));
+        assert_eq!(expand_home("~user/"), PathBuf::from("~user/"));
+    }
+
+    #[test]
+    fn complete_into_bare_home_and_variables() {
+        let words: Vec<String> = complete("$CARGO_MANIFEST_DIR",
+                                          &FileFilter::default())
+            .iter()
+            .map(|c| c.word().to_string())
+            .collect();
+
+        assert!(words.contains(&"$CARGO_MANIFEST_DIR/Cargo.toml".to_string()),
+                "{:?}", words);
+        assert!(words.contains(&"$CARGO_MANIFEST_DIR/src/".to_string()),
+                "{:?}", words);
+
+        let words = complete("~", &FileFilter::new(FileKind::Dir));
+        assert!(words.iter().all(|c| c.word().starts_with("~/")));
+
+        // An unset variable is a name in the current directory
+        assert!(complete("$CMDUI_NOT_SET", &FileFilter::default()).iter()
+                .all(|c| !c.word().starts_with("$CMDUI_NOT_SET/")));
+    }
+
+    #[test]
+    fn complete_lists_whole_directory() {
+        let dir = TestDir::new("list", &["demoapp.rs", "lib.rs", "bin/",
+                                         ".hidden"]);
+
+        // Names are matched by the completer, in any match mode
+        assert_eq!(dir.words("dmo"), ["bin/", "demoapp.rs", "lib.rs"]);
+        assert_eq!(dir.words("."), [".hidden", "bin/", "demoapp.rs",
+                                    "lib.rs"]);
+    }
+}
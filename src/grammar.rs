@@ -0,0 +1,1187 @@
+use std::collections::HashSet;
+
+use regex::Regex;
+
+use crate::cmdui::CmdApp;
+use crate::args::{Args, FromArg};
+use crate::cmdline::Arg;
+use crate::files::{FileFilter, FileKind};
+use crate::suggest::{did_you_mean, suggestions};
+
+// Command patterns are compiled into a small nondeterministic automaton.
+// Each word in the pattern, a keyword or a <placeholder>, is a transition
+// between two states. Optional parts, alternatives and repetitions are
+// built with epsilon transitions. A command line matches the pattern if
+// its words lead from the start state to the accept state.
+//
+// Pattern syntax:
+//
+//   keyword        The word itself
+//   <name>         Any word. <int> and <bool> are checked for type
+//   <name:a|b>     One of the words a or b
+//   <name:(?i)a|b> One of the words a or b, in any case
+//   <name:0..=9>   A number in a range, see NumRange
+//   <name:/re/>    A word matching the regular expression re
+//   <name:*.x|*.y> A file name, completed from files matching a glob
+//   [ ... ]        Optional part
+//   ( a | b c )    Alternatives
+//   x...           One or more repetitions of x
+
+// What values a placeholder accepts, besides the checks of its type
+enum Constraint {
+    None,
+    // The allowed values of an enumerated placeholder, as in
+    // <mode:fast|safe|off>, and whether they are matched case insensitively,
+    // as in <mode:(?i)fast|safe|off>
+    Values(Vec<String>, bool),
+    // A numeric range, as in <int:0..=100>
+    Range(NumRange),
+    // Glob patterns of the files offered when completing, as in
+    // <filename:*.csv|*.tsv>. Values are not checked against them.
+    Glob(Vec<String>),
+    // A regular expression the whole value must match, as in
+    // <name:/[a-z]+/>. The expression is kept as written, with slashes.
+    Regex(String, Regex),
+}
+
+// A range of numbers, written as in Rust: a..b excludes b, a..=b includes
+// it, and either bound may be left out. Integer bounds, which may have a
+// radix prefix, only accept integers.
+struct NumRange {
+    text: String,
+    start: Option<f64>,
+    end: Option<f64>,
+    inclusive: bool,
+    integer: bool,
+}
+
+impl NumRange {
+    // Parse a range, or return None if the text is not a range
+    fn parse(text: &str) -> Option<Self> {
+        let (start, end) = text.split_once("..")?;
+        let (end, inclusive) = match end.strip_prefix('=') {
+            Some(end) => { (end, true) },
+            None => { (end, false) },
+        };
+
+        if start.trim().is_empty() && end.trim().is_empty() {
+            return None;
+        }
+
+        let mut integer = true;
+        let mut bound = |b: &str| -> Option<Option<f64>> {
+            let b = b.trim();
+
+            if b.is_empty() {
+                return Some(None);
+            }
+
+            if let Some(n) = i64::from_arg(b) {
+                return Some(Some(n as f64));
+            }
+
+            integer = false;
+            return f64::from_arg(b).map(Some);
+        };
+
+        let start = bound(start)?;
+        let end = bound(end)?;
+
+        if inclusive && end.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            text: text.trim().to_string(),
+            start: start,
+            end: end,
+            inclusive: inclusive,
+            integer: integer,
+        })
+    }
+
+    fn kind(&self) -> &'static str {
+        return if self.integer { "an integer" } else { "a number" };
+    }
+
+    fn contains(&self, value: &str) -> bool {
+        let n = if self.integer {
+            i64::from_arg(value).map(|n| n as f64)
+        }
+        else {
+            f64::from_arg(value)
+        };
+
+        let Some(n) = n else {
+            return false;
+        };
+
+        return self.start.is_none_or(|s| n >= s)
+            && self.end.is_none_or(|e| n < e || (self.inclusive && n == e));
+    }
+}
+
+pub(crate) struct Placeholder {
+    name: String,
+    constraint: Constraint,
+}
+
+impl Placeholder {
+    // Parse the text between the angle brackets. The text after a colon is
+    // a /regex/, a range, or a list of values or globs separated by '|'.
+    fn parse(text: &str) -> Result<Self, String> {
+        let (name, constraint) = match text.split_once(':') {
+            Some((name, spec)) => {
+                let spec = spec.trim();
+
+                if spec.len() >= 2 && spec.starts_with('/')
+                    && spec.ends_with('/') {
+                    let re = &spec[1..spec.len() - 1];
+                    let regex = Regex::new(&format!("^(?:{})$", re))
+                        .map_err(|e| {
+                            format!("Bad regular expression in <{}>: {}",
+                                    text, e)
+                        })?;
+
+                    (name.trim(), Constraint::Regex(spec.to_string(), regex))
+                }
+                else if spec.contains("..")
+                    && !spec.contains(['|', '*', '?']) {
+                    let Some(range) = NumRange::parse(spec) else {
+                        return Err(format!("Bad range in <{}>", text));
+                    };
+
+                    (name.trim(), Constraint::Range(range))
+                }
+                else {
+                    let (spec, ignore_case) = match spec.strip_prefix("(?i)") {
+                        Some(spec) => { (spec, true) },
+                        None => { (spec, false) },
+                    };
+                    let values: Vec<String> = spec.split('|')
+                        .map(|v| v.trim().to_string())
+                        .collect();
+
+                    if values.iter().any(|v| v.is_empty()) {
+                        return Err(format!("Empty value in <{}>", text));
+                    }
+
+                    if values.iter().any(|v| v.contains(['*', '?'])) {
+                        (name.trim(), Constraint::Glob(values))
+                    }
+                    else {
+                        (name.trim(), Constraint::Values(values, ignore_case))
+                    }
+                }
+            },
+            None => {
+                (text.trim(), Constraint::None)
+            },
+        };
+
+        if name.is_empty() {
+            return Err("Empty placeholder".to_string());
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            constraint: constraint,
+        })
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        return &self.name;
+    }
+
+    // The placeholder as shown in help and passed to the keyword expander,
+    // e.g. <mode>
+    pub(crate) fn text(&self) -> String {
+        return format!("<{}>", self.name);
+    }
+
+    // The range or regular expression a value must satisfy, as written in
+    // the pattern, e.g. "0..=100" or "/[a-z]+/"
+    pub(crate) fn constraint(&self) -> Option<String> {
+        return match &self.constraint {
+            Constraint::Range(r) => {
+                Some(r.text.clone())
+            },
+            Constraint::Regex(text, _) => {
+                Some(text.clone())
+            },
+            _ => {
+                None
+            },
+        }
+    }
+
+    // Description of the built-in placeholder types, enumerations and
+    // constraints, for the help command
+    pub(crate) fn default_doc(&self) -> Option<String> {
+        match &self.constraint {
+            Constraint::Values(values, _) => {
+                return Some(format!("One of {}", one_of(values)));
+            },
+            Constraint::Range(r) if r.integer => {
+                return Some(format!("An integer in the range {}", r.text));
+            },
+            Constraint::Range(r) => {
+                return Some(format!("A number in the range {}", r.text));
+            },
+            Constraint::Regex(text, _) => {
+                return Some(format!("A word matching {}", text));
+            },
+            Constraint::Glob(globs) => {
+                return Some(format!("A file matching {}", one_of(globs)));
+            },
+            Constraint::None => { },
+        }
+
+        return match self.name.as_str() {
+            "bool" => {
+                Some("A boolean value: on/off, true/false, t/f or 1/0"
+                     .to_string())
+            },
+            "int" => {
+                Some("A non-negative integer, with a 0x, 0o or 0b prefix \
+                      for hex, octal or binary".to_string())
+            },
+            "dir" => { Some("A directory".to_string()) },
+            "exe" => { Some("An executable file".to_string()) },
+            _ => { None },
+        }
+    }
+
+    // The files offered when completing a placeholder with globs, or None
+    // for other placeholders. The globs of a <dir> or an <exe> apply to
+    // directories or executables.
+    pub(crate) fn file_filter(&self) -> Option<FileFilter> {
+        let Constraint::Glob(globs) = &self.constraint else {
+            return None;
+        };
+
+        let kind = match self.name.as_str() {
+            "dir" => { FileKind::Dir },
+            "exe" => { FileKind::Exe },
+            _ => { FileKind::Any },
+        };
+
+        return Some(FileFilter::new(kind).with_globs(globs));
+    }
+
+    // Whether values are checked regardless of case, as for
+    // <mode:(?i)fast|safe|off>
+    pub(crate) fn ignores_case(&self) -> bool {
+        return matches!(self.constraint, Constraint::Values(_, true));
+    }
+
+    pub(crate) fn is_enumeration(&self) -> bool {
+        return matches!(self.constraint, Constraint::Values(..));
+    }
+
+    // The valid values of an enumerated placeholder type, used for
+    // completion and suggestions
+    pub(crate) fn values(&self) -> Vec<&str> {
+        if let Constraint::Values(values, _) = &self.constraint {
+            return values.iter().map(|v| v.as_str()).collect();
+        }
+
+        return match self.name.as_str() {
+            "bool" => { vec!["on", "off", "true", "false"] },
+            _ => { vec!() },
+        }
+    }
+
+    // The value of an enumeration as written in the pattern. Values are
+    // matched exactly, unless the pattern asks to ignore case.
+    fn canonical(&self, value: &str) -> Option<&str> {
+        let Constraint::Values(values, ignore_case) = &self.constraint else {
+            return None;
+        };
+
+        return values.iter()
+            .find(|v| *v == value
+                  || (*ignore_case && v.eq_ignore_ascii_case(value)))
+            .map(|v| v.as_str());
+    }
+
+    // Check that a value is valid for the placeholder type and constraint
+    pub(crate) fn check(&self, value: &str) -> Result<(), String> {
+        let result = match &self.constraint {
+            Constraint::Values(values, _) => {
+                match self.canonical(value) {
+                    Some(_) => {
+                        Ok(())
+                    },
+                    None => {
+                        Err(format!("Expected {}, got '{}'", one_of(values),
+                                    value))
+                    },
+                }
+            },
+            Constraint::Range(r) => {
+                if r.contains(value) {
+                    Ok(())
+                }
+                else {
+                    Err(format!("Expected {} in the range {}, got '{}'",
+                                r.kind(), r.text, value))
+                }
+            },
+            Constraint::Regex(text, re) => {
+                if re.is_match(value) {
+                    Ok(())
+                }
+                else {
+                    Err(format!("Expected a word matching {}, got '{}'",
+                                text, value))
+                }
+            },
+            Constraint::Glob(_) | Constraint::None => {
+                match self.name.as_str() {
+                    "int" => {
+                        <dyn CmdApp>::parse_int(value).map(|_| ())
+                    },
+                    "bool" => {
+                        <dyn CmdApp>::parse_bool(value).map(|_| ())
+                    },
+                    _ => {
+                        Ok(())
+                    },
+                }
+            },
+        };
+
+        return result.map_err(|e| {
+            format!("{}.{}", e, did_you_mean(&suggestions(value,
+                                                          &self.values())))
+        });
+    }
+}
+
+// List values as "a, b or c"
+fn one_of(values: &[String]) -> String {
+    return match values.split_last() {
+        Some((last, rest)) if !rest.is_empty() => {
+            format!("{} or {}", rest.join(", "), last)
+        },
+        _ => {
+            values.join("")
+        },
+    }
+}
+
+pub(crate) enum Term {
+    Keyword(String),
+    Placeholder(Placeholder),
+}
+
+enum Edge {
+    Epsilon(usize),
+    // Term index and target state
+    Term(usize, usize),
+}
+
+// Position in a pattern while matching, along with the arguments bound to
+// placeholders so far, as (term index, argument index) pairs.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Thread {
+    state: usize,
+    bindings: Vec<(usize, usize)>,
+}
+
+pub(crate) struct Pattern {
+    text: String,
+    // The leading keywords, which make up the command name
+    command: Vec<String>,
+    terms: Vec<Term>,
+    edges: Vec<Vec<Edge>>,
+    accept: usize,
+}
+
+impl Pattern {
+    pub(crate) fn compile(text: &str) -> Result<Self, String> {
+        let mut pattern = Self {
+            text: text.to_string(),
+            command: vec!(),
+            terms: vec!(),
+            edges: vec!(),
+            accept: 0,
+        };
+
+        let mut parser = PatternParser {
+            text: text,
+            position: 0,
+            pattern: &mut pattern,
+        };
+
+        let (start, end) = parser.parse_sequence(true)?;
+
+        if let Some(c) = parser.peek() {
+            return Err(format!("Unexpected '{}'", c));
+        }
+
+        debug_assert_eq!(start, 0);
+        pattern.accept = end;
+
+        return Ok(pattern);
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        return &self.text;
+    }
+
+    pub(crate) fn command(&self) -> String {
+        return self.command.join(" ");
+    }
+
+    pub(crate) fn num_command_words(&self) -> usize {
+        return self.command.len();
+    }
+
+    // The placeholders of the pattern, each listed once
+    pub(crate) fn placeholders(&self) -> Vec<&Placeholder> {
+        let mut ret: Vec<&Placeholder> = vec!();
+
+        for term in &self.terms {
+            if let Term::Placeholder(p) = term
+                && !ret.iter().any(|r| r.name == p.name) {
+                ret.push(p);
+            }
+        }
+
+        return ret;
+    }
+
+    // Follow epsilon transitions from all threads
+    fn closure(&self, threads: Vec<Thread>) -> Vec<Thread> {
+        let mut seen = HashSet::new();
+        let mut stack = threads;
+        let mut ret = vec!();
+
+        while let Some(t) = stack.pop() {
+            if !seen.insert(t.clone()) {
+                continue;
+            }
+
+            for edge in &self.edges[t.state] {
+                if let Edge::Epsilon(target) = edge {
+                    stack.push(Thread {
+                        state: *target,
+                        bindings: t.bindings.clone(),
+                    });
+                }
+            }
+
+            ret.push(t);
+        }
+
+        return ret;
+    }
+
+    fn start(&self) -> Vec<Thread> {
+        return self.closure(vec![Thread {
+            state: 0,
+            bindings: vec!(),
+        }]);
+    }
+
+    // The keywords which may come next from the given threads
+    fn keywords<'p>(&'p self, threads: &[Thread]) -> Vec<&'p str> {
+        let mut ret = vec!();
+
+        for t in threads {
+            for edge in &self.edges[t.state] {
+                if let Edge::Term(term, _) = edge
+                    && let Term::Keyword(k) = &self.terms[*term] {
+                    ret.push(k.as_str());
+                }
+            }
+        }
+
+        return ret;
+    }
+
+    // Advance the threads past a word. The word matches a keyword if it
+    // was resolved to that keyword, and matches any placeholder.
+    fn step(&self, threads: &[Thread], i: usize, keyword: Option<&str>)
+            -> Vec<Thread> {
+        let mut next = vec!();
+
+        for t in threads {
+            for edge in &self.edges[t.state] {
+                let Edge::Term(term, target) = edge else {
+                    continue;
+                };
+
+                if let Term::Keyword(k) = &self.terms[*term]
+                    && keyword != Some(k.as_str()) {
+                    continue;
+                }
+
+                let mut bindings = t.bindings.clone();
+                bindings.push((*term, i));
+
+                next.push(Thread {
+                    state: *target,
+                    bindings: bindings,
+                });
+            }
+        }
+
+        return self.closure(next);
+    }
+
+    // Check the arguments bound to placeholders in a complete match
+    fn check(&self, thread: &Thread, args: &[Arg]) -> Result<(), String> {
+        for (term, i) in &thread.bindings {
+            if let Term::Placeholder(p) = &self.terms[*term] {
+                p.check(args[*i].as_str())?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    // The terms which may follow the given threads. The flag tells
+    // whether more words may follow the term.
+    fn next_terms(&self, threads: &[Thread]) -> Vec<(&Term, bool)> {
+        let mut ret = vec!();
+
+        for t in threads {
+            for edge in &self.edges[t.state] {
+                if let Edge::Term(term, target) = edge {
+                    let after = self.closure(vec![Thread {
+                        state: *target,
+                        bindings: vec!(),
+                    }]);
+                    let more = after.iter().any(|a| {
+                        self.edges[a.state].iter()
+                            .any(|e| matches!(e, Edge::Term(_, _)))
+                    });
+
+                    ret.push((&self.terms[*term], more));
+                }
+            }
+        }
+
+        return ret;
+    }
+}
+
+struct PatternParser<'a> {
+    text: &'a str,
+    position: usize,
+    pattern: &'a mut Pattern,
+}
+
+impl<'a> PatternParser<'a> {
+    fn peek(&mut self) -> Option<char> {
+        let rest = &self.text[self.position..];
+        let trimmed = rest.trim_start();
+        self.position += rest.len() - trimmed.len();
+
+        return trimmed.chars().next();
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.peek() == Some(c) {
+            self.position += 1;
+            return Ok(());
+        }
+        else {
+            return Err(format!("Missing '{}'", c));
+        }
+    }
+
+    fn new_state(&mut self) -> usize {
+        self.pattern.edges.push(vec!());
+        return self.pattern.edges.len() - 1;
+    }
+
+    fn epsilon(&mut self, from: usize, to: usize) {
+        self.pattern.edges[from].push(Edge::Epsilon(to));
+    }
+
+    fn term(&mut self, term: Term) -> (usize, usize) {
+        let start = self.new_state();
+        let end = self.new_state();
+        self.pattern.terms.push(term);
+        let index = self.pattern.terms.len() - 1;
+        self.pattern.edges[start].push(Edge::Term(index, end));
+
+        return (start, end);
+    }
+
+    // Parse alternatives separated by '|', up to the closing bracket
+    fn parse_alternatives(&mut self, closing: char)
+                          -> Result<(usize, usize), String> {
+        let start = self.new_state();
+        let end = self.new_state();
+
+        loop {
+            let (s, e) = self.parse_sequence(false)?;
+            self.epsilon(start, s);
+            self.epsilon(e, end);
+
+            if self.peek() == Some('|') {
+                self.position += 1;
+            }
+            else {
+                self.expect(closing)?;
+                break;
+            }
+        }
+
+        return Ok((start, end));
+    }
+
+    fn parse_sequence(&mut self, top_level: bool)
+                      -> Result<(usize, usize), String> {
+        let start = self.new_state();
+        let mut current = start;
+        let mut leading = top_level;
+
+        loop {
+            let (s, e) = match self.peek() {
+                None | Some(']') | Some(')') | Some('|') => {
+                    break;
+                },
+                Some('[') => {
+                    self.position += 1;
+                    let (s, e) = self.parse_alternatives(']')?;
+                    self.epsilon(s, e);
+                    leading = false;
+                    (s, e)
+                },
+                Some('(') => {
+                    self.position += 1;
+                    leading = false;
+                    self.parse_alternatives(')')?
+                },
+                Some('<') => {
+                    let rest = &self.text[self.position..];
+                    let Some(len) = rest.find('>') else {
+                        return Err("Missing '>'".to_string());
+                    };
+                    let placeholder = Placeholder::parse(&rest[1..len])?;
+
+                    self.position += len + 1;
+                    leading = false;
+                    self.term(Term::Placeholder(placeholder))
+                },
+                Some(_) => {
+                    let rest = &self.text[self.position..];
+                    let len = rest.find(|c: char| {
+                        c.is_whitespace() || "[]()|<>".contains(c)
+                    }).unwrap_or(rest.len());
+                    let len = rest[..len].find("...").unwrap_or(len);
+
+                    if len == 0 {
+                        return Err(format!("Unexpected '{}'",
+                                           &rest[..1]));
+                    }
+
+                    let word = &rest[..len];
+                    self.position += len;
+
+                    if leading && !self.text[self.position..]
+                        .starts_with("...") {
+                        self.pattern.command.push(word.to_string());
+                    }
+                    else {
+                        leading = false;
+                    }
+
+                    self.term(Term::Keyword(word.to_string()))
+                },
+            };
+
+            // A trailing ... repeats the last element
+            if self.text[self.position..].starts_with("...") {
+                self.position += 3;
+                self.epsilon(e, s);
+                leading = false;
+            }
+
+            self.epsilon(current, s);
+            current = e;
+        }
+
+        return Ok((start, current));
+    }
+}
+
+// Check that a command pattern is valid, e.g. one built at runtime from
+// the name of a plugin for CmdApp::command_list. The error tells what is
+// wrong with the pattern.
+pub fn check_pattern(pattern: &str) -> Result<(), String> {
+    return Pattern::compile(pattern).map(|_| ());
+}
+
+// The name of a placeholder written with or without angle brackets, as
+// "filename" for "<filename>"
+pub(crate) fn placeholder_name(placeholder: &str) -> &str {
+    return placeholder.trim_start_matches('<').trim_end_matches('>');
+}
+
+// The matched pattern, and the placeholder bindings, of a command line.
+pub(crate) struct Dispatch<'g> {
+    index: usize,
+    pattern: &'g Pattern,
+    bindings: Vec<(usize, usize)>,
+}
+
+impl<'g> Dispatch<'g> {
+    // Index of the matched pattern in the grammar
+    pub(crate) fn index(&self) -> usize {
+        return self.index;
+    }
+
+    pub(crate) fn command(&self) -> String {
+        return self.pattern.command();
+    }
+
+    // The arguments following the command keywords, named after the
+    // placeholders they matched. Abbreviated keywords are expanded.
+    pub(crate) fn args(&self, args: &[Arg]) -> Args {
+        let skip = self.pattern.num_command_words();
+        let mut args = args[skip..].to_vec();
+        let mut names = vec!();
+
+        for (term, i) in &self.bindings {
+            if *i < skip {
+                continue;
+            }
+
+            match &self.pattern.terms[*term] {
+                Term::Keyword(k) => {
+                    args[i - skip].set_value(k);
+                },
+                Term::Placeholder(p) => {
+                    if let Some(v) = p.canonical(args[i - skip].as_str()) {
+                        args[i - skip].set_value(v);
+                    }
+
+                    names.push((p.name().to_string(), i - skip));
+                },
+            }
+        }
+
+        return Args::new(args, names);
+    }
+}
+
+// The set of command patterns of an application.
+pub(crate) struct Grammar {
+    patterns: Vec<Pattern>,
+}
+
+impl Grammar {
+    // Compile the patterns. Patterns which don't compile are left out, and
+    // returned by index, with the error.
+    pub(crate) fn new<S: AsRef<str>>(patterns: &[S])
+                                     -> (Self, Vec<(usize, String)>) {
+        let mut compiled = vec!();
+        let mut bad = vec!();
+
+        for (i, p) in patterns.iter().enumerate() {
+            match Pattern::compile(p.as_ref()) {
+                Ok(pattern) => { compiled.push(pattern); },
+                Err(e) => { bad.push((i, e)); },
+            }
+        }
+
+        let grammar = Self {
+            patterns: compiled,
+        };
+
+        return (grammar, bad);
+    }
+
+    pub(crate) fn pattern(&self, index: usize) -> &Pattern {
+        return &self.patterns[index];
+    }
+
+    // Indices of the patterns which may start with the given words
+    pub(crate) fn matching(&self, words: &[&str]) -> Vec<usize> {
+        let run = self.run(words);
+
+        return (0..self.patterns.len())
+            .filter(|i| !run.threads[*i].is_empty())
+            .collect();
+    }
+
+    // Run the words through all patterns. Keywords may be abbreviated, as
+    // long as the abbreviation is unique among the keywords which may come
+    // next in any pattern.
+    fn run(&self, words: &[&str]) -> Run {
+        let mut run = Run {
+            threads: self.patterns.iter().map(|p| p.start()).collect(),
+            consumed: vec![0; self.patterns.len()],
+            ambiguous: None,
+            failure: None,
+        };
+
+        for (i, w) in words.iter().enumerate() {
+            let mut keywords: Vec<&str> = self.patterns.iter()
+                .zip(&run.threads)
+                .flat_map(|(p, t)| p.keywords(t))
+                .collect();
+            keywords.sort();
+            keywords.dedup();
+
+            let keyword = match resolve(w, &keywords) {
+                Ok(k) => { k },
+                Err(candidates) => {
+                    if run.ambiguous.is_none() {
+                        let what = if i == 0 { "command" } else { "keyword" };
+                        run.ambiguous = Some(format!(
+                            "Ambiguous {} '{}'. Candidates: {}",
+                            what, w, candidates.join(", ")));
+                    }
+                    None
+                },
+            };
+
+            for (p, pattern) in self.patterns.iter().enumerate() {
+                if run.threads[p].is_empty() {
+                    continue;
+                }
+
+                run.threads[p] = pattern.step(&run.threads[p], i, keyword);
+
+                if !run.threads[p].is_empty() {
+                    run.consumed[p] = i + 1;
+                }
+            }
+
+            if run.threads.iter().all(|t| t.is_empty()) {
+                run.failure = Some((i, keywords.iter()
+                                    .map(|k| k.to_string())
+                                    .collect()));
+                break;
+            }
+        }
+
+        return run;
+    }
+
+    // Find the pattern matching a command line, with valid placeholder
+    // values. If several patterns match, the one matching the most
+    // keywords is used, so that e.g. "show all" is preferred over
+    // "show <name>". Patterns matching equally well are tried in order.
+    // Otherwise, an error message is returned, with usage for the commands
+    // which matched the longest part of the line.
+    pub(crate) fn dispatch(&self, args: &[Arg]) -> Result<Dispatch<'_>, String> {
+        let words: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        let run = self.run(&words);
+        let mut best = 0;
+        let mut candidates = vec!();
+        let mut check_error = None;
+        let mut found: Option<(usize, Dispatch)> = None;
+
+        for (index, pattern) in self.patterns.iter().enumerate() {
+            let threads = &run.threads[index];
+            let consumed = run.consumed[index];
+
+            for t in threads.iter().filter(|t| t.state == pattern.accept) {
+                match pattern.check(t, args) {
+                    Ok(()) => {
+                        let keywords = t.bindings.iter()
+                            .filter(|(term, _)| {
+                                matches!(pattern.terms[*term],
+                                         Term::Keyword(_))
+                            })
+                            .count();
+
+                        if found.as_ref().is_none_or(|(k, _)| keywords > *k) {
+                            found = Some((keywords, Dispatch {
+                                index: index,
+                                pattern: pattern,
+                                bindings: t.bindings.clone(),
+                            }));
+                        }
+                    },
+                    Err(e) => {
+                        if check_error.is_none() {
+                            check_error = Some((e, pattern));
+                        }
+                    },
+                }
+            }
+
+            if consumed > best {
+                best = consumed;
+                candidates.clear();
+            }
+
+            if consumed == best && best > 0 {
+                candidates.push(pattern);
+            }
+        }
+
+        if let Some((_, dispatch)) = found {
+            return Ok(dispatch);
+        }
+
+        if let Some((e, pattern)) = check_error {
+            return Err(format!("{}\n{}", e, usage(&[pattern])));
+        }
+
+        if let Some(e) = run.ambiguous {
+            return Err(e);
+        }
+
+        // Suggest keywords close to the word where matching failed
+        let mut msg = String::new();
+
+        if let Some((i, keywords)) = run.failure {
+            let suggested = did_you_mean(&suggestions(words[i], &keywords));
+
+            if i == 0 {
+                return Err(format!("Unknown command '{}'.{}",
+                                   words[i], suggested));
+            }
+
+            if !suggested.is_empty() {
+                msg = format!("Unknown keyword '{}'.{}\n", words[i],
+                              suggested);
+            }
+        }
+
+        return Err(msg + &usage(&candidates));
+    }
+
+    // The terms which may follow the given words, in any pattern, along
+    // with the index of the pattern
+    pub(crate) fn next_terms(&self, words: &[&str])
+                             -> Vec<(usize, &Term, bool)> {
+        let run = self.run(words);
+        let mut ret = vec!();
+
+        for (i, p) in self.patterns.iter().enumerate() {
+            for (term, more) in p.next_terms(&run.threads[i]) {
+                ret.push((i, term, more));
+            }
+        }
+
+        return ret;
+    }
+}
+
+// Threads alive in each pattern after running a list of words through the
+// grammar, and the number of words each pattern consumed. If no pattern
+// matched all words, failure holds the index of the first word no pattern
+// matched, and the keywords allowed in its place.
+struct Run {
+    threads: Vec<Vec<Thread>>,
+    consumed: Vec<usize>,
+    ambiguous: Option<String>,
+    failure: Option<(usize, Vec<String>)>,
+}
+
+// Resolve a word to the keyword it equals, or is a unique prefix of. An
+// ambiguous prefix gives the list of candidates.
+fn resolve<'k>(word: &str, keywords: &[&'k str])
+               -> Result<Option<&'k str>, Vec<&'k str>> {
+    if word.is_empty() {
+        return Ok(None);
+    }
+
+    if let Some(k) = keywords.iter().find(|k| **k == word) {
+        return Ok(Some(k));
+    }
+
+    let candidates: Vec<&str> = keywords.iter()
+        .filter(|k| k.starts_with(word))
+        .copied()
+        .collect();
+
+    return match candidates.len() {
+        0 => { Ok(None) },
+        1 => { Ok(Some(candidates[0])) },
+        _ => { Err(candidates) },
+    }
+}
+
+fn usage(patterns: &[&Pattern]) -> String {
+    let mut ret = String::new();
+
+    for (i, p) in patterns.iter().enumerate() {
+        if i == 0 {
+            ret.push_str("Usage: ");
+        }
+        else {
+            ret.push_str("\n       ");
+        }
+
+        ret.push_str(p.as_str());
+    }
+
+    return ret;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmdline::CommandLine;
+
+    fn grammar(patterns: &[&str]) -> Grammar {
+        let (grammar, bad) = Grammar::new(patterns);
+        assert!(bad.is_empty(), "bad patterns: {:?}", bad);
+        return grammar;
+    }
+
+    // Dispatch a line, giving the index of the matched pattern, the command
+    // and the argument values
+    fn dispatch(grammar: &Grammar, line: &str)
+                -> Result<(usize, String, Vec<String>), String> {
+        let cl = CommandLine::new(line.to_string());
+        let args: Vec<Arg> = cl.parse().unwrap().iter()
+            .map(|p| p.to_arg())
+            .collect();
+        let d = grammar.dispatch(&args)?;
+        let values = d.args(&args).iter()
+            .map(|a| a.as_str().to_string())
+            .collect();
+
+        return Ok((d.index(), d.command(), values));
+    }
+
+    #[test]
+    fn compile_finds_command_keywords() {
+        let p = Pattern::compile("set attr1 <bool>").unwrap();
+        assert_eq!(p.command(), "set attr1");
+        assert_eq!(p.num_command_words(), 2);
+
+        let p = Pattern::compile("show [all]").unwrap();
+        assert_eq!(p.command(), "show");
+    }
+
+    #[test]
+    fn compile_reports_bad_patterns() {
+        assert_eq!(Pattern::compile("run <x").err().unwrap(), "Missing '>'");
+        assert_eq!(Pattern::compile("run [x").err().unwrap(),
+                   "Missing ']'");
+        assert!(Pattern::compile("run x)").is_err());
+        assert!(Pattern::compile("run <>").is_err());
+        assert!(Pattern::compile("run <x:a||b>").is_err());
+        assert!(Pattern::compile("run <x:/(/>").is_err());
+        assert!(check_pattern("run <x:/[a-z]+/>").is_ok());
+
+        for bad in ["<p:1..=>", "<p:..>", "<p:1..x>", "<p:..=>"] {
+            assert_eq!(Pattern::compile(&format!("run {}", bad)).err(),
+                       Some(format!("Bad range in {}", bad)));
+        }
+        assert!(check_pattern("run <p:1..>").is_ok());
+        assert!(check_pattern("run <p:..=-1>").is_ok());
+    }
+
+    #[test]
+    fn placeholder_name_strips_brackets() {
+        assert_eq!(placeholder_name("<filename>"), "filename");
+        assert_eq!(placeholder_name("filename"), "filename");
+    }
+
+    #[test]
+    fn new_returns_bad_patterns() {
+        let (grammar, bad) = Grammar::new(&["run", "bad <x", "stop"]);
+
+        assert_eq!(bad.len(), 1);
+        assert_eq!(bad[0].0, 1);
+        assert_eq!(dispatch(&grammar, "stop").unwrap().0, 1);
+    }
+
+    #[test]
+    fn dispatches_placeholders() {
+        let g = grammar(&["set attr1 <bool>", "set attr2 <int>"]);
+
+        assert_eq!(dispatch(&g, "set attr2 42").unwrap(),
+                   (1, "set attr2".to_string(), vec!["42".to_string()]));
+        assert!(dispatch(&g, "set attr2 x").is_err());
+        assert!(dispatch(&g, "set attr1 maybe").is_err());
+        assert!(dispatch(&g, "set attr1").is_err());
+        assert!(dispatch(&g, "set attr1 on off").is_err());
+    }
+
+    #[test]
+    fn expands_unique_abbreviations() {
+        let g = grammar(&["show (fast | slow)", "store", "set attr1"]);
+
+        assert_eq!(dispatch(&g, "sh f").unwrap().2, ["fast"]);
+        assert_eq!(dispatch(&g, "st").unwrap().1, "store");
+        assert_eq!(dispatch(&g, "se a").unwrap().1, "set attr1");
+
+        let e = dispatch(&g, "s").err().unwrap();
+        assert!(e.starts_with("Ambiguous command 's'"), "{}", e);
+    }
+
+    #[test]
+    fn reports_ambiguous_keywords() {
+        let g = grammar(&["set attr1 <int>", "set attr2 <int>"]);
+
+        assert_eq!(dispatch(&g, "se at 3").err().unwrap(),
+                   "Ambiguous keyword 'at'. Candidates: attr1, attr2");
+    }
+
+    #[test]
+    fn matches_optional_parts() {
+        let g = grammar(&["show [all] [<name>]"]);
+
+        assert_eq!(dispatch(&g, "show").unwrap().2, Vec::<String>::new());
+        assert_eq!(dispatch(&g, "show all").unwrap().2, ["all"]);
+        assert_eq!(dispatch(&g, "show all x").unwrap().2, ["all", "x"]);
+        assert_eq!(dispatch(&g, "show x").unwrap().2, ["x"]);
+        assert!(dispatch(&g, "show all x y").is_err());
+    }
+
+    #[test]
+    fn matches_alternatives() {
+        let g = grammar(&["log (on | off | level <int>)"]);
+
+        assert!(dispatch(&g, "log on").is_ok());
+        assert_eq!(dispatch(&g, "log level 3").unwrap().2, ["level", "3"]);
+        assert!(dispatch(&g, "log level").is_err());
+        assert!(dispatch(&g, "log").is_err());
+    }
+
+    #[test]
+    fn matches_repetitions() {
+        let g = grammar(&["rm <file>...", "add [<int>...]"]);
+
+        assert_eq!(dispatch(&g, "rm a b c").unwrap().2, ["a", "b", "c"]);
+        assert!(dispatch(&g, "rm").is_err());
+        assert!(dispatch(&g, "add").is_ok());
+        assert!(dispatch(&g, "add 1 2 x").is_err());
+    }
+
+    #[test]
+    fn prefers_patterns_with_more_keywords() {
+        let g = grammar(&["show <name>", "show all"]);
+
+        assert_eq!(dispatch(&g, "show all").unwrap().0, 1);
+        assert_eq!(dispatch(&g, "show x").unwrap().0, 0);
+    }
+
+    #[test]
+    fn checks_constraints() {
+        let g = grammar(&["vol <v:0..=100>", "name <n:/[a-z]+/>",
+                          "mode <m:fast|safe>", "case <c:(?i)on|off>"]);
+
+        assert!(dispatch(&g, "vol 100").is_ok());
+        assert!(dispatch(&g, "vol 101").is_err());
+        assert!(dispatch(&g, "name abc").is_ok());
+        assert!(dispatch(&g, "name Abc").is_err());
+        assert!(dispatch(&g, "mode SAFE").is_err());
+        assert_eq!(dispatch(&g, "case OFF").unwrap().2, ["off"]);
+    }
+
+    #[test]
+    fn reports_unknown_commands() {
+        let g = grammar(&["show", "store"]);
+
+        let e = dispatch(&g, "shwo").err().unwrap();
+        assert!(e.starts_with("Unknown command 'shwo'"), "{}", e);
+    }
+}
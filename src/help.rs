@@ -0,0 +1,295 @@
+use console::measure_text_width;
+
+use crate::commands::CommandSet;
+use crate::grammar::{placeholder_name, Grammar, Placeholder};
+use crate::suggest::{did_you_mean, suggestions};
+
+// Pattern of the built-in help command
+pub(crate) const HELP_PATTERN: &str = "help [<topic>...]";
+
+// Word wrap a text to the given width. Line breaks in the text are kept.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut ret = vec!();
+
+    for line in text.lines() {
+        let mut current = String::new();
+
+        for word in line.split_whitespace() {
+            if !current.is_empty()
+                && measure_text_width(&current) + 1
+                + measure_text_width(word) > width {
+                ret.push(current);
+                current = String::new();
+            }
+
+            if !current.is_empty() {
+                current.push(' ');
+            }
+
+            current.push_str(word);
+        }
+
+        ret.push(current);
+    }
+
+    return ret;
+}
+
+// Output of the help command, for the commands of a grammar. Built-in
+// commands have their summary and description in builtin_docs, at the
+// index of the pattern.
+pub(crate) struct Help<'h, A: ?Sized> {
+    pub(crate) grammar: &'h Grammar,
+    pub(crate) patterns: &'h [String],
+    pub(crate) builtin_docs: &'h [Option<(&'static str, &'static str)>],
+    pub(crate) commands: &'h CommandSet<A>,
+    pub(crate) width: usize,
+}
+
+impl<'h, A: ?Sized> Help<'h, A> {
+    // Lines of help for a topic. Without a topic, all commands are listed
+    // with their summaries. A topic is either the start of a command, or a
+    // placeholder such as <filename>. If a single command matches, its
+    // full description is shown.
+    pub(crate) fn lines(&self, topic: &[&str]) -> Result<Vec<String>, String> {
+        if topic.is_empty() {
+            let all: Vec<usize> = (0..self.patterns.len()).collect();
+            return Ok(self.list(&all));
+        }
+
+        // A placeholder, as in "help <filename>"
+        if topic.len() == 1 && topic[0].starts_with('<') {
+            let name = placeholder_name(topic[0]);
+
+            for i in 0..self.patterns.len() {
+                for p in self.grammar.pattern(i).placeholders() {
+                    if p.name() == name {
+                        let doc = self.placeholder_doc(p).unwrap_or_default();
+                        return Ok(columns(&[(p.text(), doc)], self.width));
+                    }
+                }
+            }
+
+            return Err(format!("No help for '{}'.", topic[0]));
+        }
+
+        let matching = self.grammar.matching(topic);
+
+        if matching.len() == 1 {
+            return Ok(self.details(matching[0]));
+        }
+        else if !matching.is_empty() {
+            return Ok(self.list(&matching));
+        }
+
+        let first_words: Vec<&str> = self.patterns.iter()
+            .filter_map(|p| p.split_whitespace().next())
+            .collect();
+
+        return Err(format!("No help for '{}'.{}", topic.join(" "),
+                           did_you_mean(&suggestions(topic[0],
+                                                     &first_words))));
+    }
+
+    // Summary and description of a command
+    fn doc(&self, index: usize) -> (&str, &str) {
+        let pattern = self.patterns[index].as_str();
+
+        if let Some(d) = self.commands.doc(pattern) {
+            return (&d.summary, &d.description);
+        }
+
+        return self.builtin_docs[index].unwrap_or(("", ""));
+    }
+
+    fn placeholder_doc(&self, p: &Placeholder) -> Option<String> {
+        return self.commands.placeholder_doc(p.name())
+            .map(|d| d.to_string())
+            .or_else(|| p.default_doc());
+    }
+
+    fn list(&self, indices: &[usize]) -> Vec<String> {
+        let entries: Vec<(String, String)> = indices.iter()
+            .map(|i| (self.patterns[*i].clone(), self.doc(*i).0.to_string()))
+            .collect();
+
+        return columns(&entries, self.width);
+    }
+
+    fn details(&self, index: usize) -> Vec<String> {
+        let indent = "  ";
+        let text_width = self.width.saturating_sub(indent.len()).max(20);
+        let mut ret = vec![format!("Usage: {}", self.patterns[index])];
+        let (summary, description) = self.doc(index);
+
+        for text in [summary, description] {
+            if text.is_empty() {
+                continue;
+            }
+
+            ret.push(String::new());
+            for l in wrap(text, text_width) {
+                ret.push(format!("{}{}", indent, l));
+            }
+        }
+
+        let placeholders: Vec<(String, String)> = self.grammar.pattern(index)
+            .placeholders()
+            .into_iter()
+            .filter_map(|p| {
+                self.placeholder_doc(p).map(|d| (p.text(), d))
+            })
+            .collect();
+
+        if !placeholders.is_empty() {
+            ret.push(String::new());
+            for l in columns(&placeholders, text_width) {
+                ret.push(format!("{}{}", indent, l));
+            }
+        }
+
+        return ret;
+    }
+}
+
+// Lay out names with descriptions in two columns. The descriptions are
+// wrapped to fit the terminal. A name too wide for the first column gets
+// a line of its own.
+fn columns(entries: &[(String, String)], width: usize) -> Vec<String> {
+    let padding = 2;
+    let max_name = entries.iter()
+        .map(|(n, _)| measure_text_width(n))
+        .max()
+        .unwrap_or(0);
+    let col = (max_name + padding).min(width/2);
+    let mut ret = vec!();
+
+    for (name, description) in entries {
+        if description.is_empty() {
+            ret.push(name.clone());
+            continue;
+        }
+
+        let mut lines = wrap(description, width.saturating_sub(col).max(20))
+            .into_iter();
+
+        if measure_text_width(name) + padding <= col {
+            let first = lines.next().unwrap_or_default();
+            let pad = col - measure_text_width(name);
+            ret.push(format!("{}{}{}", name, " ".repeat(pad), first));
+        }
+        else {
+            ret.push(name.clone());
+        }
+
+        for l in lines {
+            ret.push(format!("{}{}", " ".repeat(col), l));
+        }
+    }
+
+    return ret;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct App;
+
+    fn help_lines(commands: &CommandSet<App>, topic: &[&str])
+                  -> Result<Vec<String>, String> {
+        let patterns: Vec<String> = commands.patterns()
+            .map(|p| p.to_string())
+            .collect();
+        let (grammar, _) = Grammar::new(&patterns);
+        let builtin_docs = vec![None; patterns.len()];
+        let help = Help {
+            grammar: &grammar,
+            patterns: &patterns,
+            builtin_docs: &builtin_docs,
+            commands: commands,
+            width: 40,
+        };
+
+        return help.lines(topic);
+    }
+
+    fn commands() -> CommandSet<App> {
+        let mut commands = CommandSet::new();
+        commands.command("set attr <int>", |_, _| Ok(()))
+            .command("set mode <mode:fast|slow>", |_, _| Ok(()))
+            .command("run", |_, _| Ok(()))
+            .describe("set attr <int>", "Set the attribute", "")
+            .describe("run", "Run", "Runs the application until it is \
+                                      stopped or runs out of work.")
+            .describe_placeholder("<int>", "Value of the attribute");
+        return commands;
+    }
+
+    #[test]
+    fn wrap_breaks_lines_at_width() {
+        assert_eq!(wrap("one two three four", 9),
+                   ["one two", "three", "four"]);
+        assert_eq!(wrap("a\nb c", 10), ["a", "b c"]);
+        assert_eq!(wrap("unbreakable", 4), ["unbreakable"]);
+        assert_eq!(wrap("", 10), Vec::<String>::new());
+    }
+
+    #[test]
+    fn columns_align_descriptions() {
+        let entries = vec![("run".to_string(), "Run it".to_string()),
+                           ("stop".to_string(), String::new()),
+                           ("set attr".to_string(), "Set it".to_string())];
+
+        assert_eq!(columns(&entries, 80), ["run       Run it", "stop",
+                                           "set attr  Set it"]);
+    }
+
+    #[test]
+    fn columns_wrap_long_descriptions() {
+        let entries = vec![("a-very-long-name".to_string(),
+                            "one two three".to_string())];
+
+        // The name is too wide for the first column, which is at most half
+        // the width, and gets a line of its own
+        assert_eq!(columns(&entries, 20), ["a-very-long-name",
+                                           "          one two three"]);
+    }
+
+    #[test]
+    fn lines_list_all_commands() {
+        // The first column takes at most half the width
+        assert_eq!(help_lines(&commands(), &[]).unwrap(),
+                   ["set attr <int>      Set the attribute",
+                    "set mode <mode:fast|slow>",
+                    "run                 Run"]);
+    }
+
+    #[test]
+    fn lines_show_single_command() {
+        assert_eq!(help_lines(&commands(), &["run"]).unwrap(),
+                   ["Usage: run", "", "  Run", "",
+                    "  Runs the application until it is",
+                    "  stopped or runs out of work."]);
+        assert_eq!(help_lines(&commands(), &["set", "m"]).unwrap(),
+                   ["Usage: set mode <mode:fast|slow>", "",
+                    "  <mode>  One of fast or slow"]);
+        assert_eq!(help_lines(&commands(), &["set"]).unwrap(),
+                   ["set attr <int>      Set the attribute",
+                    "set mode <mode:fast|slow>"]);
+    }
+
+    #[test]
+    fn lines_show_placeholders() {
+        assert_eq!(help_lines(&commands(), &["<int>"]).unwrap(),
+                   ["<int>  Value of the attribute"]);
+        assert!(help_lines(&commands(), &["<nothing>"]).is_err());
+    }
+
+    #[test]
+    fn lines_suggest_commands() {
+        let e = help_lines(&commands(), &["rnu"]).err().unwrap();
+        assert!(e.starts_with("No help for 'rnu'."), "{}", e);
+        assert!(e.contains("run"), "{}", e);
+    }
+}
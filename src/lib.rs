@@ -1,3 +1,28 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+mod args;
+mod builtins;
+mod candidate;
 mod cmdui;
+mod commands;
+mod cmdline;
+mod error;
+mod expanders;
+mod files;
+mod grammar;
+mod help;
+mod matching;
+mod mode;
+mod parse;
+mod suggest;
 
-pub use crate::cmdui::{CmdUI, CmdApp, CommandPart, KeywordExpander};
+pub use crate::args::{Args, FromArg};
+pub use crate::builtins::Builtin;
+pub use crate::candidate::Candidate;
+pub use crate::cmdui::{CmdUI, CmdApp, KeywordExpander};
+pub use crate::commands::CommandSet;
+pub use crate::cmdline::{Arg, CommandPart, ParseError, ParseErrorKind};
+pub use crate::error::{CmdError, CmdErrorKind};
+pub use crate::grammar::check_pattern;
+pub use crate::matching::MatchMode;
+pub use crate::mode::Mode;
@@ -0,0 +1,223 @@
+// Matching of completion candidates against the word being completed.
+
+use std::collections::HashMap;
+
+// How candidates are matched against the word being completed. Prefix
+// matching is the default. Substring and fuzzy matching ignore case.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum MatchMode {
+    // The candidate starts with the word
+    #[default]
+    Prefix,
+    // The word occurs anywhere in the candidate
+    Substring,
+    // The characters of the word occur in the candidate in the same
+    // order, as in "atr2" for "attr2"
+    Fuzzy,
+}
+
+impl MatchMode {
+    // The cost of a match, or None if the candidate doesn't match. Lower
+    // is better: a match at the start of the candidate beats one further
+    // in, and a fuzzy match with the characters close together beats one
+    // with them spread out.
+    pub(crate) fn cost(&self, word: &str, candidate: &str) -> Option<usize> {
+        return match self {
+            MatchMode::Prefix => {
+                candidate.starts_with(word).then_some(0)
+            },
+            MatchMode::Substring => {
+                candidate.to_lowercase().find(&word.to_lowercase())
+            },
+            MatchMode::Fuzzy => {
+                fuzzy_cost(&word.to_lowercase(), &candidate.to_lowercase())
+            },
+        }
+    }
+}
+
+// The characters skipped when finding the characters of the word in the
+// candidate, leftmost first. Skipped characters after the first match are
+// counted twice, so that matches close together rank higher.
+fn fuzzy_cost(word: &str, candidate: &str) -> Option<usize> {
+    let mut chars = word.chars().peekable();
+    let mut cost = 0;
+    let mut started = false;
+
+    for c in candidate.chars() {
+        let Some(&w) = chars.peek() else {
+            break;
+        };
+
+        if c == w {
+            chars.next();
+            started = true;
+        }
+        else {
+            cost += if started { 2 } else { 1 };
+        }
+    }
+
+    return chars.peek().is_none().then_some(cost);
+}
+
+// Limit on the number of recently used values remembered for ranking
+const MAX_RECENT: usize = 100;
+
+// The match modes of a CmdUI, for all words and for single placeholders,
+// along with the values recently used in commands. Candidates are ranked
+// by the cost of the match, and with substring and fuzzy matching, by how
+// recently they were used.
+#[derive(Clone, Default)]
+pub(crate) struct Matching {
+    pub(crate) mode: MatchMode,
+    // Match modes by placeholder name
+    pub(crate) placeholders: HashMap<String, MatchMode>,
+    // Argument values of executed commands, the most recent last
+    recent: Vec<String>,
+}
+
+impl Matching {
+    // The match mode of a placeholder, by name, or of keywords if None
+    pub(crate) fn mode(&self, placeholder: Option<&str>) -> MatchMode {
+        return placeholder.and_then(|p| self.placeholders.get(p))
+            .copied()
+            .unwrap_or(self.mode);
+    }
+
+    // The rank of a candidate, or None if it doesn't match the word.
+    // Lower ranks are listed first. Prefix matching ignores case only for
+    // values which are checked regardless of case, as with <m:(?i)a|b>.
+    pub(crate) fn rank(&self, mode: MatchMode, word: &str, candidate: &str,
+                       ignore_case: bool) -> Option<(usize, usize)> {
+        let cost = match mode {
+            MatchMode::Prefix if ignore_case => {
+                mode.cost(&word.to_lowercase(), &candidate.to_lowercase())?
+            },
+            _ => {
+                mode.cost(word, candidate)?
+            },
+        };
+
+        if mode == MatchMode::Prefix {
+            return Some((cost, 0));
+        }
+
+        let age = self.recent.iter().rev()
+            .position(|r| r == candidate)
+            .unwrap_or(MAX_RECENT);
+
+        return Some((cost, age));
+    }
+
+    pub(crate) fn remember(&mut self, value: &str) {
+        self.recent.retain(|r| r != value);
+        self.recent.push(value.to_string());
+
+        if self.recent.len() > MAX_RECENT {
+            self.recent.remove(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_cost_finds_characters_in_order() {
+        assert_eq!(fuzzy_cost("atr2", "attr2"), Some(2));
+        assert_eq!(fuzzy_cost("attr2", "attr2"), Some(0));
+        assert_eq!(fuzzy_cost("", "attr2"), Some(0));
+        assert_eq!(fuzzy_cost("2rta", "attr2"), None);
+        assert_eq!(fuzzy_cost("attr3", "attr2"), None);
+    }
+
+    #[test]
+    fn fuzzy_cost_prefers_characters_close_together() {
+        // Skipping before the first match costs less than skipping between
+        // matches
+        assert_eq!(fuzzy_cost("ab", "xxab"), Some(2));
+        assert_eq!(fuzzy_cost("ab", "axxb"), Some(4));
+        assert!(fuzzy_cost("dmo", "demo") < fuzzy_cost("dmo", "dxmxo"));
+    }
+
+    #[test]
+    fn modes_match_differently() {
+        assert_eq!(MatchMode::Prefix.cost("at", "attr"), Some(0));
+        assert_eq!(MatchMode::Prefix.cost("tr", "attr"), None);
+        assert_eq!(MatchMode::Prefix.cost("AT", "attr"), None);
+        assert_eq!(MatchMode::Substring.cost("TR", "attr"), Some(2));
+        assert_eq!(MatchMode::Substring.cost("ta", "attr"), None);
+        assert_eq!(MatchMode::Fuzzy.cost("AR", "attr"), Some(4));
+    }
+
+    #[test]
+    fn rank_orders_by_cost() {
+        let m = Matching::default();
+        let rank = |c: &str| m.rank(MatchMode::Fuzzy, "dmo", c, false);
+        let mut ranked: Vec<&str> = ["xdemo", "dxmxo", "demo", "other"]
+            .into_iter()
+            .filter(|c| rank(c).is_some())
+            .collect();
+        ranked.sort_by_key(|c| rank(c));
+
+        assert_eq!(ranked, ["demo", "xdemo", "dxmxo"]);
+    }
+
+    #[test]
+    fn rank_puts_recent_values_first() {
+        let rank = |m: &Matching, word: &str, c: &str| {
+            m.rank(MatchMode::Substring, word, c, false).unwrap()
+        };
+        let mut m = Matching::default();
+        m.remember("banana");
+        m.remember("apple");
+
+        assert!(rank(&m, "", "apple") < rank(&m, "", "banana"));
+        assert!(rank(&m, "", "banana") < rank(&m, "", "cherry"));
+
+        // Using a value again makes it the most recent
+        m.remember("banana");
+        assert!(rank(&m, "", "banana") < rank(&m, "", "apple"));
+
+        // A better match comes first, however recent the other is
+        assert!(rank(&m, "a", "apple") < rank(&m, "a", "banana"));
+    }
+
+    #[test]
+    fn rank_ignores_case_if_asked() {
+        let m = Matching::default();
+
+        assert_eq!(m.rank(MatchMode::Prefix, "f", "Fast", false), None);
+        assert_eq!(m.rank(MatchMode::Prefix, "f", "Fast", true),
+                   Some((0, 0)));
+        assert_eq!(m.rank(MatchMode::Prefix, "FA", "fast", true),
+                   Some((0, 0)));
+    }
+
+    #[test]
+    fn rank_ignores_recency_for_prefix_matching() {
+        let mut m = Matching::default();
+        m.remember("banana");
+
+        assert_eq!(m.rank(MatchMode::Prefix, "b", "banana", false),
+                   Some((0, 0)));
+        assert_eq!(m.rank(MatchMode::Prefix, "b", "berry", false),
+                   Some((0, 0)));
+    }
+
+    #[test]
+    fn remember_forgets_oldest_values() {
+        let mut m = Matching::default();
+
+        for i in 0..=MAX_RECENT {
+            m.remember(&i.to_string());
+        }
+
+        assert_eq!(m.rank(MatchMode::Fuzzy, "", "0", false),
+                   Some((0, MAX_RECENT)));
+        assert_eq!(m.rank(MatchMode::Fuzzy, "", "1", false),
+                   Some((0, MAX_RECENT - 1)));
+    }
+}
@@ -0,0 +1,146 @@
+use rustyline::history::DefaultHistory;
+
+use crate::args::Args;
+use crate::cmdui::KeywordExpander;
+use crate::commands::{check_described, check_registered, CommandSet, Handler};
+use crate::error::CmdError;
+
+// Pattern of the built-in command leaving all modes
+pub(crate) const END_PATTERN: &str = "end";
+
+// A command mode, with its own commands, keyword expander and history. A
+// mode is entered with a command of its parent mode, and changes the
+// prompt to "(name)> ":
+//
+//   let mut config = Mode::new("config", Some(&kw_exp));
+//   config.command("hostname <name>", |app, args| { ... });
+//   ui.mode("configure", config);
+//
+// In a mode, "exit" returns to the parent mode and "end" to the top level.
+// A handler set with on_enter gets the arguments of the entry command, as
+// in "interface <name>".
+pub struct Mode<'a, A: ?Sized> {
+    name: String,
+    opt_kw_exp: Option<&'a dyn KeywordExpander>,
+    commands: CommandSet<A>,
+    // Entry pattern and mode of each sub-mode
+    modes: Vec<(String, Mode<'a, A>)>,
+    on_enter: Option<Handler<A>>,
+    history: DefaultHistory,
+}
+
+impl<'a, A: ?Sized> Mode<'a, A> {
+    pub fn new(name: &str, opt_kw_exp: Option<&'a dyn KeywordExpander>)
+               -> Self {
+        Self {
+            name: name.to_string(),
+            opt_kw_exp: opt_kw_exp,
+            commands: CommandSet::new(),
+            modes: vec!(),
+            on_enter: None,
+            history: DefaultHistory::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        return &self.name;
+    }
+
+    // Register a command with its handler, see CmdUI::command
+    #[track_caller]
+    pub fn command<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where F: FnMut(&mut A, &Args) -> Result<(), CmdError> + 'static
+    {
+        self.commands.command(pattern, handler);
+        return self;
+    }
+
+    pub fn add_commands(&mut self, commands: CommandSet<A>) -> &mut Self {
+        self.commands.extend(commands);
+        return self;
+    }
+
+    // Register a sub-mode, entered with the given command pattern. Panics
+    // if the pattern is not valid.
+    #[track_caller]
+    pub fn mode(&mut self, pattern: &str, mode: Mode<'a, A>) -> &mut Self {
+        check_registered(pattern);
+        self.modes.push((pattern.to_string(), mode));
+        return self;
+    }
+
+    // Set a handler called with the arguments of the command entering the
+    // mode. The mode is not entered if the handler returns an error.
+    pub fn on_enter<F>(&mut self, handler: F) -> &mut Self
+    where F: FnMut(&mut A, &Args) -> Result<(), CmdError> + 'static
+    {
+        self.on_enter = Some(Box::new(handler));
+        return self;
+    }
+
+    // Attach help texts to a command or sub-mode of this mode. Panics if
+    // the pattern is not registered in the mode.
+    #[track_caller]
+    pub fn describe(&mut self, pattern: &str, summary: &str,
+                    description: &str) -> &mut Self {
+        check_described(pattern,
+                        self.commands.patterns().chain(self.mode_patterns()));
+        self.commands.insert_doc(pattern, summary, description);
+        return self;
+    }
+
+    pub fn describe_placeholder(&mut self, placeholder: &str,
+                                description: &str) -> &mut Self {
+        self.commands.describe_placeholder(placeholder, description);
+        return self;
+    }
+
+    pub fn commands(&self) -> &CommandSet<A> {
+        return &self.commands;
+    }
+
+    pub(crate) fn commands_mut(&mut self) -> &mut CommandSet<A> {
+        return &mut self.commands;
+    }
+
+    pub(crate) fn keyword_expander(&self) -> Option<&'a dyn KeywordExpander> {
+        return self.opt_kw_exp;
+    }
+
+    // Entry patterns of the sub-modes
+    pub(crate) fn mode_patterns(&self) -> impl Iterator<Item = &str> {
+        return self.modes.iter().map(|(p, _)| p.as_str());
+    }
+
+    pub(crate) fn enter(&mut self, app: &mut A, args: &Args)
+                        -> Result<(), CmdError> {
+        return match &mut self.on_enter {
+            Some(handler) => { handler(app, args) },
+            None => { Ok(()) },
+        }
+    }
+
+    pub(crate) fn history(&self) -> &DefaultHistory {
+        return &self.history;
+    }
+
+    pub(crate) fn history_mut(&mut self) -> &mut DefaultHistory {
+        return &mut self.history;
+    }
+
+    // The mode reached by following a path of sub-mode indices
+    pub(crate) fn descendant(&self, path: &[usize]) -> &Mode<'a, A> {
+        return match path.split_first() {
+            Some((i, rest)) => { self.modes[*i].1.descendant(rest) },
+            None => { self },
+        }
+    }
+
+    pub(crate) fn descendant_mut(&mut self, path: &[usize])
+                                 -> &mut Mode<'a, A> {
+        return match path.split_first() {
+            Some((i, rest)) => { self.modes[*i].1.descendant_mut(rest) },
+            None => { self },
+        }
+    }
+}
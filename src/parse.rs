@@ -0,0 +1,179 @@
+// Parsing of argument values with a radix prefix or a unit.
+
+use std::time::Duration;
+
+// Split an integer literal into its digits, with the sign kept, and its
+// radix, given by a 0x, 0o or 0b prefix.
+pub(crate) fn radix(value: &str) -> Option<(String, u32)> {
+    let (sign, rest) = match value.strip_prefix(['+', '-']) {
+        Some(rest) => { (&value[..1], rest) },
+        None => { ("", value) },
+    };
+
+    let (digits, radix) = match rest.get(..2) {
+        Some("0x") | Some("0X") => { (&rest[2..], 16) },
+        Some("0o") | Some("0O") => { (&rest[2..], 8) },
+        Some("0b") | Some("0B") => { (&rest[2..], 2) },
+        _ => { (rest, 10) },
+    };
+
+    if digits.is_empty() || digits.starts_with(['+', '-']) {
+        return None;
+    }
+
+    return Some((format!("{}{}", sign, digits), radix));
+}
+
+// Split a value such as "1.5G" into its number and unit
+fn split_unit(value: &str) -> (&str, &str) {
+    let len = value.find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+
+    return (&value[..len], &value[len..]);
+}
+
+// A byte size, with an optional unit. K, M, G, T and P, with or without a
+// trailing iB, are powers of 1024. kB, MB, GB, TB and PB are powers of
+// 1000. Units are case insensitive, and the number may have a fraction.
+pub(crate) fn size(value: &str) -> Option<u64> {
+    let (number, unit) = split_unit(value);
+
+    let multiplier: u64 = match unit.to_lowercase().as_str() {
+        "" | "b" => { 1 },
+        "k" | "kib" => { 1 << 10 },
+        "m" | "mib" => { 1 << 20 },
+        "g" | "gib" => { 1 << 30 },
+        "t" | "tib" => { 1 << 40 },
+        "p" | "pib" => { 1 << 50 },
+        "kb" => { 1_000 },
+        "mb" => { 1_000_000 },
+        "gb" => { 1_000_000_000 },
+        "tb" => { 1_000_000_000_000 },
+        "pb" => { 1_000_000_000_000_000 },
+        _ => { return None; },
+    };
+
+    // Whole numbers are computed exactly
+    if let Ok(n) = number.parse::<u64>() {
+        return n.checked_mul(multiplier);
+    }
+
+    let n: f64 = number.parse().ok()?;
+    let bytes = (n*multiplier as f64).round();
+
+    if bytes >= u64::MAX as f64 {
+        return None;
+    }
+
+    return Some(bytes as u64);
+}
+
+// A duration, as one or more numbers with units, e.g. "250ms" or "1h30m".
+// The units are ns, us, ms, s, m, h and d. A plain number is seconds.
+pub(crate) fn duration(value: &str) -> Option<Duration> {
+    if value.is_empty() {
+        return None;
+    }
+
+    if let Ok(secs) = value.parse::<f64>() {
+        return Duration::try_from_secs_f64(secs).ok();
+    }
+
+    let mut rest = value;
+    let mut total = Duration::ZERO;
+
+    while !rest.is_empty() {
+        let (number, tail) = split_unit(rest);
+        let unit_len = tail.find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(tail.len());
+        let unit = &tail[..unit_len];
+
+        let secs: f64 = match unit {
+            "ns" => { 1e-9 },
+            "us" | "µs" => { 1e-6 },
+            "ms" => { 1e-3 },
+            "s" => { 1.0 },
+            "m" | "min" => { 60.0 },
+            "h" => { 3600.0 },
+            "d" => { 86400.0 },
+            _ => { return None; },
+        };
+
+        let n: f64 = number.parse().ok()?;
+        total = total.checked_add(Duration::try_from_secs_f64(n*secs).ok()?)?;
+        rest = &tail[unit_len..];
+    }
+
+    return Some(total);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn radix_reads_prefixes() {
+        assert_eq!(radix("42"), Some(("42".to_string(), 10)));
+        assert_eq!(radix("0x2a"), Some(("2a".to_string(), 16)));
+        assert_eq!(radix("0O17"), Some(("17".to_string(), 8)));
+        assert_eq!(radix("0b101"), Some(("101".to_string(), 2)));
+        assert_eq!(radix("-0x2a"), Some(("-2a".to_string(), 16)));
+        assert_eq!(radix("+7"), Some(("+7".to_string(), 10)));
+    }
+
+    #[test]
+    fn radix_rejects_missing_digits() {
+        assert_eq!(radix(""), None);
+        assert_eq!(radix("-"), None);
+        assert_eq!(radix("0x"), None);
+        assert_eq!(radix("0x-1"), None);
+        assert_eq!(radix("--1"), None);
+    }
+
+    #[test]
+    fn size_reads_units() {
+        assert_eq!(size("512"), Some(512));
+        assert_eq!(size("512b"), Some(512));
+        assert_eq!(size("1K"), Some(1024));
+        assert_eq!(size("1kib"), Some(1024));
+        assert_eq!(size("2M"), Some(2 << 20));
+        assert_eq!(size("1GiB"), Some(1 << 30));
+        assert_eq!(size("1kB"), Some(1000));
+        assert_eq!(size("3MB"), Some(3_000_000));
+        assert_eq!(size("1.5G"), Some(3 << 29));
+        assert_eq!(size("0.5k"), Some(512));
+    }
+
+    #[test]
+    fn size_rejects_bad_values() {
+        assert_eq!(size(""), None);
+        assert_eq!(size("K"), None);
+        assert_eq!(size("1X"), None);
+        assert_eq!(size("-1"), None);
+        assert_eq!(size("1.2.3M"), None);
+        assert_eq!(size("16777216P"), None);
+        assert_eq!(size("99999999999P"), None);
+    }
+
+    #[test]
+    fn duration_reads_units() {
+        assert_eq!(duration("2"), Some(Duration::from_secs(2)));
+        assert_eq!(duration("0.5"), Some(Duration::from_millis(500)));
+        assert_eq!(duration("250ms"), Some(Duration::from_millis(250)));
+        assert_eq!(duration("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(duration("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(duration("2min"), Some(Duration::from_secs(120)));
+        assert_eq!(duration("1h30m"), Some(Duration::from_secs(5400)));
+        assert_eq!(duration("1d"), Some(Duration::from_secs(86400)));
+        assert_eq!(duration("1s500ms"), Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn duration_rejects_bad_values() {
+        assert_eq!(duration(""), None);
+        assert_eq!(duration("s"), None);
+        assert_eq!(duration("-1"), None);
+        assert_eq!(duration("10x"), None);
+        assert_eq!(duration("1h 30m"), None);
+    }
+}
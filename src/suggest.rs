@@ -0,0 +1,149 @@
+// Suggestions for mistyped words, based on edit distance.
+
+// Optimal string alignment distance between two words. Counts insertions,
+// deletions, substitutions and transpositions of adjacent characters.
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    return d[a.len()][b.len()];
+}
+
+// The candidates closest to a word, if they are close enough to be likely
+// typos. About one edit per three characters is allowed.
+pub(crate) fn suggestions<'c, S: AsRef<str>>(word: &str, candidates: &'c [S])
+                                             -> Vec<&'c str> {
+    let limit = word.chars().count().div_ceil(3).max(1);
+    let mut best = limit + 1;
+    let mut ret = vec!();
+
+    for c in candidates {
+        let c = c.as_ref();
+        let d = edit_distance(&word.to_lowercase(), &c.to_lowercase());
+
+        if d < best {
+            best = d;
+            ret.clear();
+        }
+
+        if d == best && d <= limit && !ret.contains(&c) {
+            ret.push(c);
+        }
+    }
+
+    ret.sort();
+
+    return ret;
+}
+
+// Format suggestions as " Did you mean 'a' or 'b'?", or an empty string if
+// there are none.
+pub(crate) fn did_you_mean(suggestions: &[&str]) -> String {
+    let quoted: Vec<String> = suggestions.iter()
+        .map(|s| format!("'{}'", s))
+        .collect();
+
+    return match quoted.split_last() {
+        None => {
+            String::new()
+        },
+        Some((last, [])) => {
+            format!(" Did you mean {}?", last)
+        },
+        Some((last, rest)) => {
+            format!(" Did you mean {} or {}?", rest.join(", "), last)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_distance_counts_edits() {
+        assert_eq!(edit_distance("attr", "attr"), 0);
+        assert_eq!(edit_distance("attr", "atr"), 1);
+        assert_eq!(edit_distance("atr", "attr"), 1);
+        assert_eq!(edit_distance("attr", "atxr"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn edit_distance_counts_transpositions_once() {
+        assert_eq!(edit_distance("sotre", "store"), 1);
+        assert_eq!(edit_distance("ab", "ba"), 1);
+        // Optimal string alignment doesn't edit a transposed pair again
+        assert_eq!(edit_distance("ca", "abc"), 3);
+    }
+
+    #[test]
+    fn suggestions_allow_one_edit_per_three_characters() {
+        // "sto" allows one edit, and "stoer" two
+        assert_eq!(suggestions("sto", &["stop", "start"]), ["stop"]);
+        assert_eq!(suggestions("stoer", &["store", "stop"]), ["store"]);
+        assert_eq!(suggestions("stoxyz", &["store"]), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn suggestions_stop_at_limit() {
+        // One edit beyond the limit is not suggested, even as the best match
+        assert_eq!(suggestions("abc", &["axy"]), Vec::<&str>::new());
+        assert_eq!(suggestions("abc", &["axc"]), ["axc"]);
+        assert_eq!(suggestions("x", &["yy"]), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn suggestions_keep_all_ties() {
+        assert_eq!(suggestions("sat", &["set", "sit", "sate", "sit"]),
+                   ["sate", "set", "sit"]);
+        // A closer match replaces the ones found before it
+        assert_eq!(suggestions("sett", &["sat", "set", "settle"]), ["set"]);
+    }
+
+    #[test]
+    fn suggestions_ignore_case() {
+        assert_eq!(suggestions("STOP", &["stop"]), ["stop"]);
+    }
+
+    #[test]
+    fn suggestions_may_be_empty() {
+        assert_eq!(suggestions("run", &Vec::<String>::new()),
+                   Vec::<&str>::new());
+        assert_eq!(suggestions("run", &["quit", "exit"]), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn did_you_mean_lists_suggestions() {
+        assert_eq!(did_you_mean(&[]), "");
+        assert_eq!(did_you_mean(&["set"]), " Did you mean 'set'?");
+        assert_eq!(did_you_mean(&["set", "sit"]),
+                   " Did you mean 'set' or 'sit'?");
+        assert_eq!(did_you_mean(&["a", "b", "c"]),
+                   " Did you mean 'a', 'b' or 'c'?");
+    }
+}